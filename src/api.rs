@@ -1,10 +1,14 @@
-use crate::cache::set_multiple_blocks_async;
-use crate::reader::read_blocks;
+use crate::cache::{is_missing_block_skipped, set_multiple_blocks_async};
+use crate::compression::EncodedBlock;
+use crate::fields::FieldSelection;
+use crate::index::{index_blocks_async, IndexError};
+use crate::reader::{ArchiveBlock, ArchiveError};
 use crate::types::*;
 use crate::*;
-use actix_web::ResponseError;
+use actix_web::{HttpResponseBuilder, ResponseError};
 use reqwest::header::HeaderName;
-use serde_json::json;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
 use std::time::Duration;
@@ -15,23 +19,50 @@ const EXPECTED_CACHED_BLOCKS: BlockHeight = 10;
 // 1 year cache for blocks. Blocks don't change.
 const DEFAULT_CACHE_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
 const MAX_WAIT_BLOCKS: BlockHeight = 10;
+const MAX_BLOCKS_RANGE: BlockHeight = 100;
+const STREAM_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 enum ServiceError {
     ArgumentError,
     CacheError(String),
+    RedisError,
+    IndexError(String),
+    /// The server doesn't have the block index, see `INDEX_PATH`.
+    IndexNotEnabled,
+    ArchiveError(String),
     InternalDataError,
 }
 
 #[derive(Debug)]
 enum BlockOrResponse {
-    Block(String),
+    Block(EncodedBlock),
+    /// The block can't be read from the archive.
+    ArchiveError(ArchiveError),
+    Response(HttpResponse),
+}
+
+#[derive(Debug)]
+enum BlocksOrResponse {
+    Blocks {
+        blocks: Vec<String>,
+        /// The last finalized block height in the cache, unless the blocks were all in memory.
+        last_block_height: Option<BlockHeight>,
+    },
     Response(HttpResponse),
 }
 
 impl From<redis::RedisError> for ServiceError {
     fn from(_err: redis::RedisError) -> Self {
-        ServiceError::CacheError("Redis error".to_string())
+        ServiceError::RedisError
+    }
+}
+
+impl From<IndexError> for ServiceError {
+    fn from(err: IndexError) -> Self {
+        tracing::error!(target: TARGET_API, "Index error: {}", err);
+        ServiceError::IndexError("Index error".to_string())
     }
 }
 
@@ -40,6 +71,10 @@ impl fmt::Display for ServiceError {
         match *self {
             ServiceError::ArgumentError => write!(f, "Invalid argument"),
             ServiceError::CacheError(ref err) => write!(f, "Cache error: {}", err),
+            ServiceError::RedisError => write!(f, "Cache error: Redis error"),
+            ServiceError::IndexError(ref err) => write!(f, "Index error: {}", err),
+            ServiceError::IndexNotEnabled => write!(f, "The index is not enabled"),
+            ServiceError::ArchiveError(ref err) => write!(f, "Archive error: {}", err),
             ServiceError::InternalDataError => write!(f, "Internal data error"),
         }
     }
@@ -52,6 +87,19 @@ impl ResponseError for ServiceError {
             ServiceError::CacheError(ref err) => {
                 HttpResponse::InternalServerError().json(format!("Cache error: {}", err))
             }
+            ServiceError::RedisError => {
+                HttpResponse::InternalServerError().json("Cache error: Redis error")
+            }
+            ServiceError::IndexError(ref err) => {
+                HttpResponse::InternalServerError().json(format!("Index error: {}", err))
+            }
+            ServiceError::IndexNotEnabled => HttpResponse::NotImplemented().json(json!({
+                "error": "The index is not enabled on this server",
+                "type": "INDEX_NOT_ENABLED"
+            })),
+            ServiceError::ArchiveError(ref err) => {
+                HttpResponse::InternalServerError().json(format!("Archive error: {}", err))
+            }
             ServiceError::InternalDataError => {
                 HttpResponse::InternalServerError().json("Internal data error")
             }
@@ -76,6 +124,94 @@ fn arg_finality(request: &HttpRequest) -> Finality {
     }
 }
 
+/// Parses the optional `fields` query parameter.
+fn arg_fields(request: &HttpRequest) -> Result<Option<FieldSelection>, ServiceError> {
+    let query = web::Query::<HashMap<String, String>>::from_query(request.query_string())
+        .map_err(|_| ServiceError::ArgumentError)?;
+    query
+        .get("fields")
+        .map(|fields| FieldSelection::parse(fields).ok_or(ServiceError::ArgumentError))
+        .transpose()
+}
+
+/// The encoding of the response body, negotiated by the `Accept` header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ResponseFormat {
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+impl ResponseFormat {
+    /// Returns the supported format with the highest quality in the `Accept` header, or JSON by
+    /// default. Among the formats of the same quality, the first one wins.
+    fn from_request(request: &HttpRequest) -> Self {
+        let Some(accept) = header_value(request, header::ACCEPT) else {
+            return ResponseFormat::Json;
+        };
+        let mut best_format: Option<(ResponseFormat, f32)> = None;
+        for media_range in accept.split(',') {
+            let mut parts = media_range.split(';').map(str::trim);
+            let format = match parts
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase()
+                .as_str()
+            {
+                "application/json" => ResponseFormat::Json,
+                "application/cbor" => ResponseFormat::Cbor,
+                "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                    ResponseFormat::MessagePack
+                }
+                _ => continue,
+            };
+            let q = quality(parts);
+            if q > 0.0 && best_format.is_none_or(|(_, best_q)| q > best_q) {
+                best_format = Some((format, q));
+            }
+        }
+        best_format.map_or(ResponseFormat::Json, |(format, _)| format)
+    }
+
+    fn is_json(&self) -> bool {
+        *self == ResponseFormat::Json
+    }
+
+    /// Encodes the value in this format as the response body.
+    fn respond(
+        &self,
+        mut response: HttpResponseBuilder,
+        value: &Value,
+    ) -> Result<HttpResponse, ServiceError> {
+        match self {
+            ResponseFormat::Json => Ok(response.json(value)),
+            ResponseFormat::Cbor => {
+                let mut body = Vec::new();
+                ciborium::into_writer(value, &mut body)
+                    .map_err(|_| ServiceError::InternalDataError)?;
+                Ok(response.content_type("application/cbor").body(body))
+            }
+            ResponseFormat::MessagePack => {
+                let body =
+                    rmp_serde::to_vec_named(value).map_err(|_| ServiceError::InternalDataError)?;
+                Ok(response.content_type("application/msgpack").body(body))
+            }
+        }
+    }
+}
+
+/// Returns the `q` parameter of a header list item, `1` by default.
+fn quality<'a>(mut params: impl Iterator<Item = &'a str>) -> f32 {
+    params
+        .find_map(|param| param.strip_prefix("q="))
+        .and_then(|q| q.parse().ok())
+        .unwrap_or(1.0)
+}
+
+fn header_value(request: &HttpRequest, name: HeaderName) -> Option<String> {
+    Some(request.headers().get(name)?.to_str().ok()?.to_string())
+}
+
 fn header(http_response: &HttpResponse, name: HeaderName) -> Option<String> {
     Some(
         http_response
@@ -90,11 +226,14 @@ fn header(http_response: &HttpResponse, name: HeaderName) -> Option<String> {
 pub mod v0 {
     use super::*;
     use crate::cache::finality_suffix;
-    use crate::reader::archive_filename;
+    use crate::filter::{AccountFilter, EventFilter, ReceiptFilter, StateChangeFilter};
+    use crate::index::BlockIndex;
+    use crate::pool::PoolError;
+    use crate::reader::{archive_blocks, archive_filename, read_block, read_blocks};
     use actix_web::body::MessageBody;
     use actix_web::http::header::HeaderValue;
     use reqwest::StatusCode;
-    use serde_json::Value;
+    use serde::Deserialize;
 
     #[get("/last_block/{finality}{suffix:/?.*}")]
     pub async fn get_last_block(
@@ -130,14 +269,17 @@ pub mod v0 {
 
         tracing::debug!(target: TARGET_API, "Retrieving the last block for finality {}", finality);
 
-        let last_block_height =
-            cache::get_last_block_height(app_state.redis_client.clone(), chain_id, finality)
-                .await
-                .ok_or_else(|| {
-                    ServiceError::CacheError(
-                        "The last block height is missing from the cache".to_string(),
-                    )
-                })?;
+        // If the cache is unavailable, the last block height seen by this server is served.
+        let last_block_height = app_state
+            .cache
+            .get_last_block_height(chain_id, finality)
+            .await
+            .or_else(|| app_state.cache.last_known_block_height(chain_id, finality))
+            .ok_or_else(|| {
+                ServiceError::CacheError(
+                    "The last block height is missing from the cache".to_string(),
+                )
+            })?;
         Ok(HttpResponse::Found()
             .append_header((
                 header::LOCATION,
@@ -151,6 +293,133 @@ pub mod v0 {
             .finish())
     }
 
+    #[get("/stream/{finality}")]
+    pub async fn get_stream(
+        request: HttpRequest,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let chain_id = app_state.chain_id;
+        let finality =
+            Finality::try_from(request.match_info().get("finality").unwrap().to_string())
+                .map_err(|_| ServiceError::ArgumentError)?;
+        if !app_state.is_fresh {
+            // Preserve the query string if any
+            let query_string = request.query_string();
+            let suffix = if query_string.is_empty() {
+                "".to_string()
+            } else {
+                format!("?{}", query_string)
+            };
+            // Redirect to the fresh url
+            return Ok(HttpResponse::Found()
+                .append_header((
+                    header::LOCATION,
+                    format!(
+                        "https://{}/v0/stream/{}{}",
+                        app_state.archive_config.as_ref().unwrap().domain_name,
+                        finality,
+                        suffix
+                    ),
+                ))
+                .finish());
+        }
+
+        // The event ID is the block height, so the stream resumes from the next block.
+        let last_event_id: Option<BlockHeight> = request
+            .headers()
+            .get("Last-Event-ID")
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or(ServiceError::ArgumentError)
+            })
+            .transpose()?;
+
+        let last_block_height = app_state
+            .cache
+            .get_last_block_height(chain_id, finality)
+            .await
+            .ok_or_else(|| {
+                ServiceError::CacheError(
+                    "The last block height is missing from the cache".to_string(),
+                )
+            })?;
+        let block_height = last_event_id.map_or(last_block_height, |id| id + 1);
+
+        if block_height > last_block_height + MAX_WAIT_BLOCKS {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "error": "The block is too far in the future",
+                "type": "BLOCK_DOES_NOT_EXIST"
+            })));
+        }
+        if block_height <= last_block_height.saturating_sub(EXPECTED_CACHED_BLOCKS) {
+            return Ok(HttpResponse::NotFound().json(json!({
+                "error": "The last event ID is too old to resume the stream",
+                "type": "STREAM_RESUME_TOO_OLD"
+            })));
+        }
+
+        tracing::debug!(target: TARGET_API, "Streaming {} blocks from block_height: {}", finality, block_height);
+
+        let stream = futures::stream::unfold(block_height, move |block_height| {
+            next_stream_event(app_state.clone(), block_height, finality)
+        });
+
+        Ok(HttpResponse::Ok()
+            .append_header((header::CONTENT_TYPE, "text/event-stream"))
+            .append_header((header::CACHE_CONTROL, "no-cache"))
+            .append_header(("X-Accel-Buffering", "no"))
+            .streaming(stream))
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(crate) struct Subscription {
+        from_block_height: Option<BlockHeight>,
+        #[serde(default = "default_finality")]
+        finality: Finality,
+        #[serde(default)]
+        filter: ReceiptFilter,
+    }
+
+    fn default_finality() -> Finality {
+        Finality::Final
+    }
+
+    #[get("/ws")]
+    pub async fn get_ws(
+        request: HttpRequest,
+        body: web::Payload,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        if !app_state.is_fresh {
+            // Preserve the query string if any
+            let query_string = request.query_string();
+            let suffix = if query_string.is_empty() {
+                "".to_string()
+            } else {
+                format!("?{}", query_string)
+            };
+            // Redirect to the fresh url
+            return Ok(HttpResponse::Found()
+                .append_header((
+                    header::LOCATION,
+                    format!(
+                        "https://{}/v0/ws{}",
+                        app_state.archive_config.as_ref().unwrap().domain_name,
+                        suffix
+                    ),
+                ))
+                .finish());
+        }
+
+        let (response, session, msg_stream) =
+            actix_ws::handle(&request, body).map_err(|_| ServiceError::ArgumentError)?;
+        actix_web::rt::spawn(handle_ws_session(session, msg_stream, app_state));
+        Ok(response)
+    }
+
     #[get("/first_block")]
     pub async fn get_first_block(
         request: HttpRequest,
@@ -200,9 +469,24 @@ pub mod v0 {
     ) -> Result<impl Responder, ServiceError> {
         let finality = arg_finality(&request);
         let block_height: BlockHeight = arg(&request, "block_height")?;
-        let response = get_block_inner(block_height, finality, app_state).await?;
+        let fields = arg_fields(&request)?;
+        // The block can be returned as is, unless it has to be projected or converted.
+        let is_raw = fields.is_none() && ResponseFormat::from_request(&request).is_json();
+        let accept_encoding = is_raw
+            .then(|| header_value(&request, header::ACCEPT_ENCODING))
+            .flatten();
+        let response = get_block_inner(
+            block_height,
+            finality,
+            app_state,
+            accept_encoding.as_deref(),
+        )
+        .await?;
 
-        redirect_or_map(request, response, "", |block_json| Ok(block_json))
+        if response.status() == StatusCode::OK && is_raw {
+            return Ok(response);
+        }
+        redirect_or_map(request, response, "", Ok)
     }
 
     #[get("/block{finality:(_opt)?}/{block_height}/headers")]
@@ -212,7 +496,7 @@ pub mod v0 {
     ) -> Result<impl Responder, ServiceError> {
         let finality = arg_finality(&request);
         let block_height: BlockHeight = arg(&request, "block_height")?;
-        let response = get_block_inner(block_height, finality, app_state).await?;
+        let response = get_block_inner(block_height, finality, app_state, None).await?;
 
         redirect_or_map(request, response, "/headers", |block_json| {
             Ok(block_json.get("block").cloned().unwrap_or(Value::Null))
@@ -228,7 +512,7 @@ pub mod v0 {
         let block_height: BlockHeight = arg(&request, "block_height")?;
         let shard_id: u64 = arg(&request, "shard_id")?;
 
-        let response = get_block_inner(block_height, finality, app_state).await?;
+        let response = get_block_inner(block_height, finality, app_state, None).await?;
 
         redirect_or_map(
             request,
@@ -259,7 +543,7 @@ pub mod v0 {
         let block_height: BlockHeight = arg(&request, "block_height")?;
         let shard_id: u64 = arg(&request, "shard_id")?;
 
-        let response = get_block_inner(block_height, finality, app_state.clone()).await?;
+        let response = get_block_inner(block_height, finality, app_state, None).await?;
 
         redirect_or_map(
             request,
@@ -280,109 +564,560 @@ pub mod v0 {
         )
     }
 
-    fn redirect_or_map<F>(
+    #[get("/block{finality:(_opt)?}/{block_height}/tx/{tx_hash}")]
+    pub async fn get_transaction(
         request: HttpRequest,
-        mut response: HttpResponse,
-        suffix: &str,
-        f: F,
-    ) -> Result<impl Responder, ServiceError>
-    where
-        F: FnOnce(Value) -> Result<Value, ServiceError>,
-    {
-        match response.status() {
-            StatusCode::FOUND => {
-                let previous_location = header(&response, header::LOCATION).unwrap();
-                // Preserve the query string if any
-                let query_string = request.query_string();
-                let suffix = if query_string.is_empty() {
-                    suffix.to_string()
-                } else {
-                    format!("{}?{}", suffix, query_string)
-                };
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let finality = arg_finality(&request);
+        let block_height: BlockHeight = arg(&request, "block_height")?;
+        let tx_hash: String = arg(&request, "tx_hash")?;
 
-                response.headers_mut().insert(
-                    header::LOCATION,
-                    HeaderValue::from_str(&format!("{}{}", previous_location, suffix)).unwrap(),
-                );
-                Ok(response)
+        let response = get_block_inner(block_height, finality, app_state, None).await?;
+
+        redirect_or_map(
+            request,
+            response,
+            &format!("/tx/{tx_hash}"),
+            move |block_json| {
+                let transaction = block_shards(&block_json).find_map(|shard| {
+                    shard["chunk"]["transactions"]
+                        .as_array()?
+                        .iter()
+                        .find(|tx| tx["transaction"]["hash"].as_str() == Some(&tx_hash))
+                        .map(|tx| (shard["shard_id"].clone(), tx))
+                });
+                let receipt_execution_outcomes: Vec<Value> = block_shards(&block_json)
+                    .filter_map(|shard| shard["receipt_execution_outcomes"].as_array())
+                    .flatten()
+                    .filter(|outcome| outcome["tx_hash"].as_str() == Some(&tx_hash))
+                    .cloned()
+                    .collect();
+                if transaction.is_none() && receipt_execution_outcomes.is_empty() {
+                    return Ok(Value::Null);
+                }
+                let (shard_id, transaction) = transaction.unzip();
+                Ok(json!({
+                    "shard_id": shard_id,
+                    "transaction": transaction.map(|tx| &tx["transaction"]),
+                    "outcome": transaction.map(|tx| &tx["outcome"]),
+                    "receipt_execution_outcomes": receipt_execution_outcomes,
+                }))
+            },
+        )
+    }
+
+    #[get("/block{finality:(_opt)?}/{block_height}/account/{account_id}")]
+    pub async fn get_account_activity(
+        request: HttpRequest,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let finality = arg_finality(&request);
+        let block_height: BlockHeight = arg(&request, "block_height")?;
+        let account_id: String = arg(&request, "account_id")?;
+
+        let response = get_block_inner(block_height, finality, app_state, None).await?;
+
+        let filter = AccountFilter {
+            account_id: account_id.clone(),
+        };
+        redirect_or_map(
+            request,
+            response,
+            &format!("/account/{account_id}"),
+            move |block_json| {
+                if block_json.is_null() {
+                    return Ok(Value::Null);
+                }
+                Ok(json!({
+                    "shards": filter.filter_shards(&block_json),
+                }))
+            },
+        )
+    }
+
+    #[get("/block{finality:(_opt)?}/{block_height}/events")]
+    pub async fn get_events(
+        request: HttpRequest,
+        filter: web::Query<EventFilter>,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let finality = arg_finality(&request);
+        let block_height: BlockHeight = arg(&request, "block_height")?;
+
+        let response = get_block_inner(block_height, finality, app_state, None).await?;
+
+        let filter = filter.into_inner();
+        redirect_or_map(request, response, "/events", move |block_json| {
+            if block_json.is_null() {
+                return Ok(Value::Null);
             }
-            StatusCode::OK => {
-                // We need to grab the CACHE_CONTROL header from the response and return it
-                let cache_control_header = header(&response, header::CACHE_CONTROL).unwrap();
+            Ok(Value::Array(filter.extract_events(&block_json)))
+        })
+    }
 
-                let body_bytes = response.into_body().try_into_bytes().unwrap();
-                let block_json: Value = serde_json::from_slice(&body_bytes)
-                    .map_err(|_| ServiceError::InternalDataError)?;
-                f(block_json).and_then(|block_json| {
-                    Ok(HttpResponse::Ok()
-                        .insert_header((header::CACHE_CONTROL, cache_control_header))
-                        .json(block_json))
-                })
+    #[get("/block{finality:(_opt)?}/{block_height}/state_changes")]
+    pub async fn get_state_changes(
+        request: HttpRequest,
+        filter: web::Query<StateChangeFilter>,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let finality = arg_finality(&request);
+        let block_height: BlockHeight = arg(&request, "block_height")?;
+
+        let response = get_block_inner(block_height, finality, app_state, None).await?;
+
+        let filter = filter.into_inner();
+        redirect_or_map(request, response, "/state_changes", move |block_json| {
+            if block_json.is_null() {
+                return Ok(Value::Null);
             }
-            _ => Ok(response),
-        }
+            Ok(Value::Array(filter.extract_state_changes(&block_json)))
+        })
     }
 
-    /// Retrieves a block based on the given block height and finality.
-    ///
-    /// This function checks if the block height is within valid limits and handles redirects
-    /// to archive URLs if necessary. It then attempts to retrieve the block from the cache
-    /// or archive, and returns the block data as an HTTP response.
-    ///
-    /// # Arguments
-    ///
-    /// * `block_height` - The height of the block to retrieve.
-    /// * `finality` - The finality of the block to retrieve (e.g., Final, Optimistic).
-    /// * `app_state` - The application state containing configuration and cache information.
-    ///
-    /// # Returns
-    ///
-    /// An HTTP response containing the block data or an error message.
-    async fn get_block_inner(
-        block_height: BlockHeight,
-        finality: Finality,
+    #[get("/blocks/{from_block_height}/{to_block_height}")]
+    pub async fn get_blocks(
+        request: HttpRequest,
         app_state: web::Data<AppState>,
-    ) -> Result<HttpResponse, ServiceError> {
-        let chain_id = app_state.chain_id.clone();
+    ) -> Result<impl Responder, ServiceError> {
+        let from_block_height: BlockHeight = arg(&request, "from_block_height")?;
+        let to_block_height: BlockHeight = arg(&request, "to_block_height")?;
+        let fields = arg_fields(&request)?;
+        let format = ResponseFormat::from_request(&request);
 
-        // Check if the block height is within valid limits
-        if let Some(response) = check_block_height_limits(block_height, &app_state) {
+        if to_block_height < from_block_height {
+            return Err(ServiceError::ArgumentError);
+        }
+        if to_block_height - from_block_height >= MAX_BLOCKS_RANGE {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": format!("The block range can't exceed {} blocks", MAX_BLOCKS_RANGE),
+                "type": "BLOCK_RANGE_TOO_LARGE"
+            })));
+        }
+
+        // Check if both ends of the range are within valid limits
+        if let Some(response) = check_block_height_limits(from_block_height, &app_state) {
             return Ok(response);
         }
+        if let Some(response) = check_block_height_limits(to_block_height, &app_state) {
+            return Ok(response);
+        }
+
+        // Preserve the query string if any
+        let query_string = request.query_string();
+        let suffix = if query_string.is_empty() {
+            "".to_string()
+        } else {
+            format!("?{}", query_string)
+        };
 
         // Handle redirects to archive URLs if necessary
-        if let Some(response) = check_archive_redirects(block_height, finality, &app_state) {
+        if let Some(response) =
+            check_archive_range_redirects(from_block_height, to_block_height, &suffix, &app_state)
+        {
             return Ok(response);
         }
 
-        tracing::debug!(target: TARGET_API, "Retrieving {} block for block_height: {}", finality, block_height);
-
-        // Retrieve the block from the cache or archive
-        let block_or_response =
-            retrieve_block_from_cache_or_archive(block_height, finality, &app_state, chain_id)
-                .await?;
+        tracing::debug!(target: TARGET_API, "Retrieving blocks from {} to {}", from_block_height, to_block_height);
 
-        let mut block = match block_or_response {
-            BlockOrResponse::Block(block) => block,
-            BlockOrResponse::Response(response) => return Ok(response),
+        let (blocks, last_block_height) = match retrieve_blocks_from_cache_or_archive(
+            from_block_height,
+            to_block_height,
+            &suffix,
+            &app_state,
+        )
+        .await?
+        {
+            BlocksOrResponse::Blocks {
+                blocks,
+                last_block_height,
+            } => (blocks, last_block_height),
+            BlocksOrResponse::Response(response) => return Ok(response),
         };
 
-        // Determine the cache duration based on whether the block is empty
-        let cache_duration = if block.is_empty() {
-            block = "null".to_string();
-            Duration::from_secs(24 * 60 * 60)
+        // Same as for a single block, the missing blocks and the blocks that are not final yet
+        // are cached for a shorter time.
+        let is_final = !blocks.iter().any(String::is_empty)
+            && last_block_height
+                .is_none_or(|last_block_height| to_block_height <= last_block_height);
+        let cache_duration = if is_final {
+            DEFAULT_CACHE_DURATION.as_secs()
         } else {
-            DEFAULT_CACHE_DURATION
+            24 * 60 * 60
         };
-
-        // Return the block data as an HTTP response
-        Ok(HttpResponse::Ok()
-            .append_header((header::CONTENT_TYPE, "application/json; charset=utf-8"))
+        let mut response = HttpResponse::Ok();
+        response
+            .append_header((header::VARY, "Accept"))
             .append_header((
                 header::CACHE_CONTROL,
-                format!("public, max-age={}", cache_duration.as_secs()),
-            ))
-            .body(block))
+                format!("public, max-age={}", cache_duration),
+            ));
+
+        if fields.is_none() && format == ResponseFormat::Json {
+            // Skipped blocks are served as `null`, same as `/v0/block`
+            let body = format!(
+                "[{}]",
+                blocks
+                    .iter()
+                    .map(|block| if block.is_empty() { "null" } else { block })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            );
+            return Ok(response
+                .append_header((header::CONTENT_TYPE, "application/json; charset=utf-8"))
+                .body(body));
+        }
+
+        let blocks = blocks
+            .iter()
+            .map(|block| {
+                if block.is_empty() {
+                    return Ok(Value::Null);
+                }
+                let block_json = serde_json::from_str::<Value>(block)
+                    .map_err(|_| ServiceError::InternalDataError)?;
+                Ok(match &fields {
+                    Some(fields) => fields.project(&block_json),
+                    None => block_json,
+                })
+            })
+            .collect::<Result<Vec<_>, ServiceError>>()?;
+        format.respond(response, &Value::Array(blocks))
+    }
+
+    #[get("/archive/{start_height}")]
+    pub async fn get_archive(
+        request: HttpRequest,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let block_height: BlockHeight = arg(&request, "start_height")?;
+        let chain_id = app_state.chain_id;
+
+        if let Some(response) = check_block_height_limits(block_height, &app_state) {
+            return Ok(response);
+        }
+        if let Some(response) = check_archive_file_redirects(block_height, &app_state) {
+            return Ok(response);
+        }
+        let Some(read_config) = app_state.read_config.as_ref() else {
+            // The server doesn't use archive files, redirect to the latest server with files.
+            let archive_config = app_state
+                .archive_config
+                .as_ref()
+                .expect("Missing archive config without local files config");
+            return Ok(HttpResponse::Found()
+                .append_header((
+                    header::CACHE_CONTROL,
+                    format!("public, max-age={}", 24 * 60 * 60),
+                ))
+                .append_header((
+                    header::LOCATION,
+                    format!(
+                        "https://a{}.{}/v0/archive/{}",
+                        archive_config.archive_boundaries.len(),
+                        archive_config.domain_name,
+                        block_height
+                    ),
+                ))
+                .finish());
+        };
+
+        // Only the first block height of the archive is served, so each archive has a single URL.
+        let start_height = block_height / read_config.save_every_n * read_config.save_every_n;
+        if start_height != block_height {
+            return Ok(HttpResponse::Found()
+                .append_header((
+                    header::CACHE_CONTROL,
+                    format!("public, max-age={}", 24 * 60 * 60),
+                ))
+                .append_header((header::LOCATION, format!("/v0/archive/{}", start_height)))
+                .finish());
+        }
+
+        let filename = archive_filename(read_config, chain_id, start_height);
+        let data = match read_config.store.read(&filename).await {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                return Ok(HttpResponse::NotFound().json(json!({
+                    "error": "The archive doesn't exist",
+                    "type": "ARCHIVE_NOT_FOUND"
+                })));
+            }
+            Err(e) => {
+                tracing::error!(target: TARGET_API, "Failed to read {}: {:?}", filename, e);
+                return Err(ServiceError::ArchiveError(
+                    "Failed to read the archive".to_string(),
+                ));
+            }
+        };
+
+        let mut response = HttpResponse::Ok();
+        response
+            .append_header((header::VARY, "Accept"))
+            .append_header((
+                header::CACHE_CONTROL,
+                format!("public, max-age={}", DEFAULT_CACHE_DURATION.as_secs()),
+            ));
+
+        if is_archive_file_requested(&request) {
+            return Ok(response.content_type("application/gzip").body(data));
+        }
+
+        let save_every_n = read_config.save_every_n;
+        let blocks = match app_state
+            .archive_read_pool
+            .run(move || archive_blocks(save_every_n, start_height, &data))
+            .await
+        {
+            Ok(blocks) => blocks,
+            Err(PoolError::Panicked) => {
+                return Err(ServiceError::ArchiveError(
+                    "Failed to decode the archive".to_string(),
+                ));
+            }
+            Err(_) => return Ok(archive_error_response(ArchiveError::Overloaded)),
+        };
+        if let Some(error) = blocks.iter().find_map(|(_, block)| match block {
+            ArchiveBlock::Unavailable(error) => Some(*error),
+            _ => None,
+        }) {
+            return Ok(archive_error_response(error));
+        }
+        // One block per line, skipped blocks are served as `null`, same as `/v0/block`
+        let body = futures::stream::iter(blocks.into_iter().map(|(_, block)| {
+            let mut line = match block {
+                ArchiveBlock::Block(block) if !block.is_empty() => block,
+                _ => "null".to_string(),
+            };
+            line.push('\n');
+            Ok::<_, actix_web::Error>(web::Bytes::from(line))
+        }));
+        Ok(response
+            .content_type("application/x-ndjson")
+            .streaming(body))
+    }
+
+    #[get("/tx/{tx_hash}")]
+    pub async fn get_tx(
+        request: HttpRequest,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let tx_hash: String = arg(&request, "tx_hash")?;
+        let location = {
+            let tx_hash = tx_hash.clone();
+            lookup_index(&app_state, move |index| index.get_tx(&tx_hash)).await?
+        };
+
+        Ok(index_redirect(
+            &request,
+            location.map(|location| format!("/v0/block/{}/tx/{}", location.block_height, tx_hash)),
+            "The transaction is not found",
+            "TX_NOT_FOUND",
+        ))
+    }
+
+    #[get("/receipt/{receipt_id}")]
+    pub async fn get_receipt(
+        request: HttpRequest,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let receipt_id: String = arg(&request, "receipt_id")?;
+        let location =
+            lookup_index(&app_state, move |index| index.get_receipt(&receipt_id)).await?;
+
+        Ok(index_redirect(
+            &request,
+            location.map(|location| {
+                format!(
+                    "/v0/block/{}/shard/{}",
+                    location.block_height, location.shard_id
+                )
+            }),
+            "The receipt is not found",
+            "RECEIPT_NOT_FOUND",
+        ))
+    }
+
+    #[get("/block_hash/{block_hash}{suffix:/?.*}")]
+    pub async fn get_block_by_hash(
+        request: HttpRequest,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let block_hash: String = arg(&request, "block_hash")?;
+        let suffix = request.match_info().get("suffix").unwrap_or_default();
+        let block_height =
+            lookup_index(&app_state, move |index| index.get_block_hash(&block_hash)).await?;
+
+        Ok(index_redirect(
+            &request,
+            block_height.map(|block_height| format!("/v0/block/{}{}", block_height, suffix)),
+            "The block hash is not found",
+            "BLOCK_HASH_NOT_FOUND",
+        ))
+    }
+
+    #[get("/chunk/{chunk_hash}")]
+    pub async fn get_chunk_by_hash(
+        request: HttpRequest,
+        app_state: web::Data<AppState>,
+    ) -> Result<impl Responder, ServiceError> {
+        let chunk_hash: String = arg(&request, "chunk_hash")?;
+        let location = lookup_index(&app_state, move |index| index.get_chunk(&chunk_hash)).await?;
+
+        Ok(index_redirect(
+            &request,
+            location.map(|location| {
+                format!(
+                    "/v0/block/{}/chunk/{}",
+                    location.block_height, location.shard_id
+                )
+            }),
+            "The chunk is not found",
+            "CHUNK_NOT_FOUND",
+        ))
+    }
+
+    fn block_shards(block_json: &Value) -> impl Iterator<Item = &Value> {
+        block_json
+            .get("shards")
+            .and_then(|shards| shards.as_array())
+            .into_iter()
+            .flatten()
+    }
+
+    fn redirect_or_map<F>(
+        request: HttpRequest,
+        mut response: HttpResponse,
+        suffix: &str,
+        f: F,
+    ) -> Result<HttpResponse, ServiceError>
+    where
+        F: FnOnce(Value) -> Result<Value, ServiceError>,
+    {
+        let fields = arg_fields(&request)?;
+        let format = ResponseFormat::from_request(&request);
+        match response.status() {
+            StatusCode::FOUND => {
+                let previous_location = header(&response, header::LOCATION).unwrap();
+                // Preserve the query string if any
+                let query_string = request.query_string();
+                let suffix = if query_string.is_empty() {
+                    suffix.to_string()
+                } else {
+                    format!("{}?{}", suffix, query_string)
+                };
+
+                response.headers_mut().insert(
+                    header::LOCATION,
+                    HeaderValue::from_str(&format!("{}{}", previous_location, suffix)).unwrap(),
+                );
+                Ok(response)
+            }
+            StatusCode::OK => {
+                // We need to grab the CACHE_CONTROL header from the response and return it
+                let cache_control_header = header(&response, header::CACHE_CONTROL).unwrap();
+
+                let body_bytes = response.into_body().try_into_bytes().unwrap();
+                let block_json: Value = serde_json::from_slice(&body_bytes)
+                    .map_err(|_| ServiceError::InternalDataError)?;
+                let block_json = f(block_json)?;
+                let block_json = match &fields {
+                    Some(fields) => fields.project(&block_json),
+                    None => block_json,
+                };
+                let mut response = HttpResponse::Ok();
+                response
+                    .insert_header((header::CACHE_CONTROL, cache_control_header))
+                    .insert_header((header::VARY, "Accept"));
+                format.respond(response, &block_json)
+            }
+            _ => Ok(response),
+        }
+    }
+
+    /// Retrieves a block based on the given block height and finality.
+    ///
+    /// This function checks if the block height is within valid limits and handles redirects
+    /// to archive URLs if necessary. It then attempts to retrieve the block from the cache
+    /// or archive, and returns the block data as an HTTP response.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - The height of the block to retrieve.
+    /// * `finality` - The finality of the block to retrieve (e.g., Final, Optimistic).
+    /// * `app_state` - The application state containing configuration and cache information.
+    /// * `accept_encoding` - The `Accept-Encoding` header of the request. If the cached block is
+    ///   compressed with an accepted encoding, it's returned without decompression.
+    ///
+    /// # Returns
+    ///
+    /// An HTTP response containing the block data or an error message.
+    async fn get_block_inner(
+        block_height: BlockHeight,
+        finality: Finality,
+        app_state: web::Data<AppState>,
+        accept_encoding: Option<&str>,
+    ) -> Result<HttpResponse, ServiceError> {
+        let chain_id = app_state.chain_id;
+
+        // Check if the block height is within valid limits
+        if let Some(response) = check_block_height_limits(block_height, &app_state) {
+            return Ok(response);
+        }
+
+        // Handle redirects to archive URLs if necessary
+        if let Some(response) = check_archive_redirects(block_height, finality, &app_state) {
+            return Ok(response);
+        }
+
+        tracing::debug!(target: TARGET_API, "Retrieving {} block for block_height: {}", finality, block_height);
+
+        // Retrieve the block from the cache or archive
+        let block_or_response =
+            retrieve_block_from_cache_or_archive(block_height, finality, &app_state, chain_id)
+                .await?;
+
+        let block = match block_or_response {
+            BlockOrResponse::Block(block) => block,
+            BlockOrResponse::ArchiveError(error) => return Ok(archive_error_response(error)),
+            BlockOrResponse::Response(response) => return Ok(response),
+        };
+
+        let mut response = HttpResponse::Ok();
+        response
+            .append_header((header::CONTENT_TYPE, "application/json; charset=utf-8"))
+            // The block is returned as is only to the JSON requests accepting its encoding
+            .append_header((header::VARY, "Accept, Accept-Encoding"));
+
+        // Determine the cache duration based on whether the block is empty
+        if block.is_empty() {
+            return Ok(response
+                .append_header((
+                    header::CACHE_CONTROL,
+                    format!("public, max-age={}", 24 * 60 * 60),
+                ))
+                .body("null"));
+        }
+        response.append_header((
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", DEFAULT_CACHE_DURATION.as_secs()),
+        ));
+
+        // Return the compressed block as is if the client accepts the encoding
+        if let Some(content_encoding) = block.compression.content_encoding() {
+            if accept_encoding.is_some_and(|value| block.compression.is_accepted(value)) {
+                return Ok(response
+                    .append_header((header::CONTENT_ENCODING, content_encoding))
+                    .body(block.data));
+            }
+        }
+
+        // Return the block data as an HTTP response
+        let block = block
+            .into_string()
+            .map_err(|_| ServiceError::InternalDataError)?;
+        Ok(response.body(block))
     }
 
     /// Checks if the block height is within valid limits.
@@ -466,11 +1201,7 @@ pub mod v0 {
                 );
             }
             // Find the required archive index
-            let index = archive_config
-                .archive_boundaries
-                .iter()
-                .position(|&x| block_height < x)
-                .unwrap_or(archive_config.archive_boundaries.len());
+            let index = archive_index(archive_config, block_height);
             if index != archive_config.archive_index {
                 return Some(
                     HttpResponse::Found()
@@ -492,6 +1223,116 @@ pub mod v0 {
         None
     }
 
+    /// Handles redirects to archive URLs for a range of finalized blocks.
+    ///
+    /// The whole range has to be served by a single archive node, so ranges crossing an archive
+    /// boundary are rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_block_height` - The first block height of the range (inclusive).
+    /// * `to_block_height` - The last block height of the range (inclusive).
+    /// * `suffix` - The query string suffix to preserve on redirect.
+    /// * `app_state` - The application state containing configuration information.
+    ///
+    /// # Returns
+    ///
+    /// An optional HTTP response indicating a redirect to an archive URL or an error.
+    fn check_archive_range_redirects(
+        from_block_height: BlockHeight,
+        to_block_height: BlockHeight,
+        suffix: &str,
+        app_state: &web::Data<AppState>,
+    ) -> Option<HttpResponse> {
+        let archive_config = app_state.archive_config.as_ref()?;
+        let index = archive_index(archive_config, from_block_height);
+        if index != archive_index(archive_config, to_block_height) {
+            return Some(HttpResponse::BadRequest().json(json!({
+                "error": "The block range crosses an archive boundary",
+                "type": "BLOCK_RANGE_CROSSES_ARCHIVE_BOUNDARY"
+            })));
+        }
+        if index != archive_config.archive_index {
+            return Some(
+                HttpResponse::Found()
+                    .append_header((
+                        header::CACHE_CONTROL,
+                        format!("public, max-age={}", 24 * 60 * 60),
+                    ))
+                    .append_header((
+                        header::LOCATION,
+                        format!(
+                            "https://a{}.{}/v0/blocks/{}/{}{}",
+                            index,
+                            archive_config.domain_name,
+                            from_block_height,
+                            to_block_height,
+                            suffix
+                        ),
+                    ))
+                    .finish(),
+            );
+        }
+        None
+    }
+
+    /// Handles redirects to the archive node storing the archive file of the given block height.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - The block height within the archive file.
+    /// * `app_state` - The application state containing configuration information.
+    ///
+    /// # Returns
+    ///
+    /// An optional HTTP response indicating a redirect to an archive URL.
+    fn check_archive_file_redirects(
+        block_height: BlockHeight,
+        app_state: &web::Data<AppState>,
+    ) -> Option<HttpResponse> {
+        let archive_config = app_state.archive_config.as_ref()?;
+        let index = archive_index(archive_config, block_height);
+        if index == archive_config.archive_index {
+            return None;
+        }
+        Some(
+            HttpResponse::Found()
+                .append_header((
+                    header::CACHE_CONTROL,
+                    format!("public, max-age={}", 24 * 60 * 60),
+                ))
+                .append_header((
+                    header::LOCATION,
+                    format!(
+                        "https://a{}.{}/v0/archive/{}",
+                        index, archive_config.domain_name, block_height
+                    ),
+                ))
+                .finish(),
+        )
+    }
+
+    /// Whether the `Accept` header asks for the archive file as stored instead of NDJSON.
+    fn is_archive_file_requested(request: &HttpRequest) -> bool {
+        header_value(request, header::ACCEPT).is_some_and(|accept| {
+            accept.split(',').any(|media_range| {
+                matches!(
+                    media_range.split(';').next().unwrap_or_default().trim(),
+                    "application/gzip" | "application/x-gzip" | "application/x-tar+gzip"
+                )
+            })
+        })
+    }
+
+    /// Returns the index of the archive boundary that is responsible for the given block height.
+    fn archive_index(archive_config: &ArchiveConfig, block_height: BlockHeight) -> usize {
+        archive_config
+            .archive_boundaries
+            .iter()
+            .position(|&x| block_height < x)
+            .unwrap_or(archive_config.archive_boundaries.len())
+    }
+
     /// Retrieves the block from the cache or archive.
     ///
     /// # Arguments
@@ -510,36 +1351,178 @@ pub mod v0 {
         app_state: &web::Data<AppState>,
         chain_id: ChainId,
     ) -> Result<BlockOrResponse, ServiceError> {
-        loop {
-            match cache::get_block_and_last_block_height(
-                app_state.redis_client.clone(),
-                chain_id.clone(),
-                block_height,
-                finality,
-            )
-            .await?
-            {
-                (Some(block), _) => return Ok(BlockOrResponse::Block(block)),
-                (_, None) => {
-                    return Err(ServiceError::CacheError(
-                        "The last block height is missing from the cache".to_string(),
-                    ));
-                }
-                (None, Some(last_block_height)) => {
-                    if let Some(block) = handle_not_cached_block(
-                        block_height,
-                        last_block_height,
-                        finality,
-                        &app_state,
-                        chain_id.clone(),
-                    )
-                    .await?
-                    {
-                        return Ok(block);
-                    }
-                }
+        if finality == Finality::Final {
+            if let Some(block) = app_state.memory_cache.get(block_height) {
+                return Ok(BlockOrResponse::Block(block));
             }
         }
+        if !can_read_without_cache(block_height, finality, app_state, chain_id) {
+            return retrieve_block_from_cache(block_height, finality, app_state, chain_id).await;
+        }
+        // The archive is the fallback, so the Redis errors are not retried.
+        match cache::fail_fast(retrieve_block_from_cache(
+            block_height,
+            finality,
+            app_state,
+            chain_id,
+        ))
+        .await
+        {
+            Err(ServiceError::RedisError) => {}
+            res => return res,
+        }
+        read_block_without_cache(block_height, finality, app_state, chain_id)
+            .await
+            .ok_or(ServiceError::RedisError)
+    }
+
+    /// Retrieves a block from the cache, reading it from the archive if it's not cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - The height of the block to retrieve.
+    /// * `finality` - The finality of the block to retrieve.
+    /// * `app_state` - The application state containing configuration and cache information.
+    /// * `chain_id` - The chain ID of the blockchain.
+    ///
+    /// # Returns
+    ///
+    /// The block or a response, or an error.
+    async fn retrieve_block_from_cache(
+        block_height: BlockHeight,
+        finality: Finality,
+        app_state: &web::Data<AppState>,
+        chain_id: ChainId,
+    ) -> Result<BlockOrResponse, ServiceError> {
+        loop {
+            let res = app_state
+                .cache
+                .get_block_and_last_block_height(chain_id, block_height, finality)
+                .await?;
+            match res {
+                (Some(block), _) => {
+                    if finality == Finality::Final {
+                        app_state.memory_cache.insert(block_height, block.clone());
+                    }
+                    return Ok(BlockOrResponse::Block(block));
+                }
+                // An archive node doesn't need the last block height to read the archive files
+                (_, None) if app_state.is_latest => {
+                    return Err(ServiceError::CacheError(
+                        "The last block height is missing from the cache".to_string(),
+                    ));
+                }
+                (None, last_block_height) => {
+                    if let Some(block) = handle_not_cached_block(
+                        block_height,
+                        last_block_height,
+                        finality,
+                        app_state,
+                        chain_id,
+                    )
+                    .await?
+                    {
+                        return Ok(block);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether a block can be read directly from the archive when the cache is unavailable.
+    ///
+    /// Only the finalized blocks are read. On the latest node, only the blocks of the complete
+    /// archive files are read, i.e. the blocks before the archive file of the last block height
+    /// seen in the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - The height of the block to retrieve.
+    /// * `finality` - The finality of the block to retrieve.
+    /// * `app_state` - The application state containing configuration and cache information.
+    /// * `chain_id` - The chain ID of the blockchain.
+    fn can_read_without_cache(
+        block_height: BlockHeight,
+        finality: Finality,
+        app_state: &web::Data<AppState>,
+        chain_id: ChainId,
+    ) -> bool {
+        let Some(read_config) = app_state.read_config.as_ref() else {
+            return false;
+        };
+        if finality != Finality::Final {
+            return false;
+        }
+        if !app_state.is_latest {
+            return true;
+        }
+        app_state
+            .cache
+            .last_known_block_height(chain_id, finality)
+            .is_some_and(|last_block_height| {
+                block_height
+                    < last_block_height / read_config.save_every_n * read_config.save_every_n
+            })
+    }
+
+    /// Reads a finalized block directly from the archive when the cache is unavailable, see
+    /// `can_read_without_cache`.
+    ///
+    /// The blocks are only cached in memory, and the archive read isn't coordinated with the other
+    /// requests, since both need the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - The height of the block to retrieve.
+    /// * `finality` - The finality of the block to retrieve.
+    /// * `app_state` - The application state containing configuration and cache information.
+    /// * `chain_id` - The chain ID of the blockchain.
+    ///
+    /// # Returns
+    ///
+    /// The block or an archive error, or `None` if the block can't be read from the archive.
+    async fn read_block_without_cache(
+        block_height: BlockHeight,
+        finality: Finality,
+        app_state: &web::Data<AppState>,
+        chain_id: ChainId,
+    ) -> Option<BlockOrResponse> {
+        if !can_read_without_cache(block_height, finality, app_state, chain_id) {
+            return None;
+        }
+        let read_config = app_state.read_config.as_ref()?;
+
+        tracing::warn!(target: TARGET_API, "The cache is unavailable, reading block {} from the archive", block_height);
+        let block = match read_block(
+            read_config,
+            &app_state.archive_read_pool,
+            chain_id,
+            block_height,
+        )
+        .await
+        {
+            Some(block) => block,
+            None => {
+                let blocks = read_blocks(
+                    read_config,
+                    &app_state.archive_read_pool,
+                    chain_id,
+                    block_height,
+                )
+                .await;
+                cache_archive_blocks_in_memory(app_state, &blocks);
+                blocks
+                    .into_iter()
+                    .find_map(|(height, block)| (height == block_height).then_some(block))?
+            }
+        };
+        cache_archive_blocks_in_memory(app_state, &[(block_height, block.clone())]);
+        Some(match block {
+            ArchiveBlock::Unavailable(error) => BlockOrResponse::ArchiveError(error),
+            block => BlockOrResponse::Block(EncodedBlock::from_string(
+                block.cached().unwrap_or_default(),
+            )),
+        })
     }
 
     /// Handles the case where the block is not cached.
@@ -547,7 +1530,8 @@ pub mod v0 {
     /// # Arguments
     ///
     /// * `block_height` - The height of the block to retrieve.
-    /// * `last_block_height` - The height of the last block in the cache.
+    /// * `last_block_height` - The height of the last block in the cache. Only the latest node
+    ///   requires it.
     /// * `finality` - The finality of the block to retrieve.
     /// * `app_state` - The application state containing configuration and cache information.
     /// * `chain_id` - The chain ID of the blockchain.
@@ -557,12 +1541,12 @@ pub mod v0 {
     /// An optional block data as a string or an error.
     async fn handle_not_cached_block(
         block_height: BlockHeight,
-        last_block_height: BlockHeight,
+        last_block_height: Option<BlockHeight>,
         finality: Finality,
         app_state: &web::Data<AppState>,
         chain_id: ChainId,
     ) -> Result<Option<BlockOrResponse>, ServiceError> {
-        if app_state.is_latest {
+        if let Some(last_block_height) = last_block_height.filter(|_| app_state.is_latest) {
             if block_height > last_block_height + MAX_WAIT_BLOCKS {
                 return Ok(Some(BlockOrResponse::Response(
                     HttpResponse::NotFound().json(json!({
@@ -573,14 +1557,15 @@ pub mod v0 {
             }
 
             if block_height > last_block_height {
-                cache::wait_for_block(
-                    app_state.redis_client.clone(),
-                    chain_id,
-                    block_height,
-                    finality,
-                    Duration::from_millis(1000 * (block_height - last_block_height + 1)),
-                )
-                .await?;
+                app_state
+                    .cache
+                    .wait_for_block(
+                        chain_id,
+                        block_height,
+                        finality,
+                        Duration::from_millis(1000 * (block_height - last_block_height + 1)),
+                    )
+                    .await?;
                 return Ok(None);
             }
 
@@ -629,16 +1614,48 @@ pub mod v0 {
             )));
         }
 
+        // A seekable archive allows reading only the requested block.
+        if let Some(block) = read_block(
+            app_state.read_config.as_ref().unwrap(),
+            &app_state.archive_read_pool,
+            chain_id,
+            block_height,
+        )
+        .await
+        {
+            cache_archive_blocks(
+                app_state,
+                chain_id,
+                finality,
+                &[(block_height, block.clone())],
+            );
+            return Ok(Some(match block {
+                ArchiveBlock::Unavailable(error) => BlockOrResponse::ArchiveError(error),
+                block => BlockOrResponse::Block(EncodedBlock::from_string(
+                    block.cached().unwrap_or_default(),
+                )),
+            }));
+        }
+
         // Before reading blocks we'll check the last time the archive was accessed and
         // indicate we want to read it.
         let archive_fn = archive_filename(
-            &app_state.read_config.as_ref().unwrap(),
+            app_state.read_config.as_ref().unwrap(),
             chain_id,
             block_height,
         );
-        let should_read =
-            cache::acquire_archive_read_attempt(app_state.redis_client.clone(), &archive_fn)
-                .await?;
+        // The attempt only saves reading the archive file twice, so it's read if the attempt fails.
+        let should_read = match app_state
+            .cache
+            .acquire_archive_read_attempt(&archive_fn)
+            .await
+        {
+            Ok(should_read) => should_read,
+            Err(err) => {
+                tracing::warn!(target: TARGET_API, "Failed to acquire the read attempt of {}: {:?}", archive_fn, err);
+                true
+            }
+        };
 
         if !should_read {
             tokio::time::sleep(Duration::from_millis(100)).await;
@@ -646,71 +1663,1618 @@ pub mod v0 {
         }
 
         let blocks = read_blocks(
-            &app_state.read_config.as_ref().unwrap(),
+            app_state.read_config.as_ref().unwrap(),
+            &app_state.archive_read_pool,
             chain_id,
             block_height,
-        );
+        )
+        .await;
         let block = blocks
             .iter()
             .find_map(|(height, block)| {
                 if *height == block_height {
-                    Some(block.as_ref().cloned().unwrap_or_default())
+                    Some(block.clone())
                 } else {
                     None
                 }
             })
             .unwrap();
-        set_multiple_blocks_async(app_state.redis_client.clone(), chain_id, finality, blocks);
-        Ok(Some(BlockOrResponse::Block(block)))
+        cache_archive_blocks(app_state, chain_id, finality, &blocks);
+        match block {
+            ArchiveBlock::Unavailable(error) => {
+                // Let the next request retry reading the archive, since the block wasn't cached.
+                if let Err(err) = app_state
+                    .cache
+                    .release_archive_read_attempt(&archive_fn)
+                    .await
+                {
+                    tracing::warn!(target: TARGET_API, "Failed to release the read attempt of {}: {:?}", archive_fn, err);
+                }
+                Ok(Some(BlockOrResponse::ArchiveError(error)))
+            }
+            block => Ok(Some(BlockOrResponse::Block(EncodedBlock::from_string(
+                block.cached().unwrap_or_default(),
+            )))),
+        }
     }
-}
 
-#[get("/health")]
-pub async fn health(app_state: web::Data<AppState>) -> Result<impl Responder, ServiceError> {
-    if !app_state.is_latest {
-        return Ok(HttpResponse::Ok().json(json!({"status": "ok"})));
+    /// Caches the available blocks read from an archive file in memory and in Redis, and indexes
+    /// them in the background. The unavailable blocks are not cached, so they are read from the
+    /// archive again.
+    fn cache_archive_blocks(
+        app_state: &web::Data<AppState>,
+        chain_id: ChainId,
+        finality: Finality,
+        blocks: &[(BlockHeight, ArchiveBlock)],
+    ) {
+        if finality == Finality::Final {
+            cache_archive_blocks_in_memory(app_state, blocks);
+        }
+        let blocks: Vec<(BlockHeight, Option<String>)> = blocks
+            .iter()
+            .filter_map(|(block_height, block)| {
+                block.cached().map(|block| (*block_height, Some(block)))
+            })
+            .collect();
+        if let Some(block_index) = &app_state.block_index {
+            index_blocks_async(block_index.clone(), blocks.clone());
+        }
+        set_multiple_blocks_async(
+            app_state.cache.clone(),
+            chain_id,
+            finality,
+            app_state.cache_compression,
+            blocks,
+        );
     }
-    let chain_id = app_state.chain_id;
-    let finality = Finality::Final;
-    let block_height =
-        cache::get_last_block_height(app_state.redis_client.clone(), chain_id, finality)
-            .await
-            .ok_or_else(|| {
-                ServiceError::CacheError(
-                    "The last block height is missing from the cache".to_string(),
-                )
-            })?;
-    match cache::get_block_and_last_block_height(
-        app_state.redis_client.clone(),
-        chain_id,
-        block_height,
-        finality,
-    )
-    .await?
-    {
-        (Some(block), _) => {
-            let block: serde_json::Value = serde_json::from_str(&block)
-                .map_err(|_| ServiceError::CacheError("Failed to parse the block".to_string()))?;
-            let timestamp = block["block"]["header"]["timestamp_nanosec"]
-                .as_str()
-                .ok_or_else(|| {
-                    ServiceError::CacheError("The block is missing a timestamp".to_string())
-                })?;
-            let t_nano = timestamp.parse::<u128>().unwrap_or(0);
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default();
-            let sync_latency_ms = now.as_nanos().saturating_sub(t_nano) / 1_000_000;
-            if sync_latency_ms > app_state.max_healthy_latency_ms {
-                return Ok(HttpResponse::Ok().json(json!({"status": "unhealthy"})));
+
+    /// Caches the finalized blocks read from an archive file in memory.
+    fn cache_archive_blocks_in_memory(
+        app_state: &web::Data<AppState>,
+        blocks: &[(BlockHeight, ArchiveBlock)],
+    ) {
+        for (block_height, block) in blocks {
+            if let ArchiveBlock::Block(block) = block {
+                app_state
+                    .memory_cache
+                    .insert(*block_height, EncodedBlock::from_string(block.clone()));
             }
         }
-        _ => {
-            return Err(ServiceError::CacheError(
-                "The block is not cached".to_string(),
-            ));
+    }
+
+    /// Returns the response for a block that can't be read from the archive. The response is not
+    /// cached, since the block may exist.
+    fn archive_error_response(error: ArchiveError) -> HttpResponse {
+        let (mut response, message, error_type) = match error {
+            ArchiveError::FileMissing => (
+                HttpResponse::ServiceUnavailable(),
+                "The archive file is missing",
+                "ARCHIVE_FILE_MISSING",
+            ),
+            ArchiveError::Corrupt => (
+                HttpResponse::InternalServerError(),
+                "The archive file is corrupt",
+                "ARCHIVE_DATA_CORRUPT",
+            ),
+            ArchiveError::Overloaded => {
+                let mut response = HttpResponse::ServiceUnavailable();
+                response.append_header((header::RETRY_AFTER, "1"));
+                (
+                    response,
+                    "Too many archive reads, try again later",
+                    "ARCHIVE_READS_OVERLOADED",
+                )
+            }
+        };
+        response
+            .append_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({
+                "error": message,
+                "type": error_type
+            }))
+    }
+
+    /// Produces the next Server-Sent Event of the block stream.
+    ///
+    /// Waits for the block to be cached, sending a keep-alive comment if nothing arrived within
+    /// `STREAM_KEEP_ALIVE_INTERVAL`. A recent block missing from the cache is sent as skipped only
+    /// once a later block confirms it, see `is_missing_block_skipped`, otherwise the stream waits
+    /// for the next block and checks again. An older block has expired and is read from the
+    /// archive. The stream ends on an error, so the client reconnects with the `Last-Event-ID`
+    /// header.
+    ///
+    /// # Arguments
+    ///
+    /// * `app_state` - The application state containing configuration and cache information.
+    /// * `block_height` - The height of the next block to send.
+    /// * `finality` - The finality of the stream.
+    ///
+    /// # Returns
+    ///
+    /// The event bytes and the next block height, or `None` to end the stream.
+    async fn next_stream_event(
+        app_state: web::Data<AppState>,
+        block_height: BlockHeight,
+        finality: Finality,
+    ) -> Option<(Result<web::Bytes, actix_web::Error>, BlockHeight)> {
+        let chain_id = app_state.chain_id;
+        let block = loop {
+            let res = app_state
+                .cache
+                .get_block_and_last_block_height(chain_id, block_height, finality)
+                .await;
+            // The block height to wait for before retrying
+            let wait_block_height = match res {
+                Ok((Some(block), _)) => break block,
+                Ok((None, Some(last_block_height)))
+                    if block_height <= last_block_height.saturating_sub(EXPECTED_CACHED_BLOCKS) =>
+                {
+                    // The block has expired from the cache
+                    match retrieve_block_from_cache_or_archive(
+                        block_height,
+                        finality,
+                        &app_state,
+                        chain_id,
+                    )
+                    .await
+                    {
+                        Ok(BlockOrResponse::Block(block)) => break block,
+                        res => {
+                            tracing::warn!(target: TARGET_API, "Stream stopped at expired block {}: {:?}", block_height, res);
+                            return None;
+                        }
+                    }
+                }
+                Ok((None, Some(last_block_height))) if block_height <= last_block_height => {
+                    let res = is_missing_block_skipped(
+                        app_state.cache.as_ref(),
+                        chain_id,
+                        block_height,
+                        last_block_height,
+                        finality,
+                    )
+                    .await;
+                    match res {
+                        Ok(Some(true)) => break EncodedBlock::from_string(String::new()),
+                        // The block is not cached yet or the later blocks don't tell yet
+                        Ok(_) => last_block_height + 1,
+                        Err(err) => {
+                            tracing::warn!(target: TARGET_API, "Stream stopped checking whether block {} was skipped: {:?}", block_height, err);
+                            return None;
+                        }
+                    }
+                }
+                Ok((None, Some(_))) => block_height,
+                Ok((None, None)) => {
+                    tracing::warn!(target: TARGET_API, "Stream stopped: the last block height is missing from the cache");
+                    return None;
+                }
+                Err(err) => {
+                    tracing::warn!(target: TARGET_API, "Stream stopped at block {}: {:?}", block_height, err);
+                    return None;
+                }
+            };
+            let res = app_state
+                .cache
+                .wait_for_block(
+                    chain_id,
+                    wait_block_height,
+                    finality,
+                    STREAM_KEEP_ALIVE_INTERVAL,
+                )
+                .await;
+            match res {
+                Ok(true) => continue,
+                Ok(false) => {
+                    return Some((
+                        Ok(web::Bytes::from_static(b": keep-alive\n\n")),
+                        block_height,
+                    ))
+                }
+                Err(err) => {
+                    tracing::warn!(target: TARGET_API, "Stream stopped waiting for block {}: {:?}", wait_block_height, err);
+                    return None;
+                }
+            }
+        };
+        let block = match block.into_string() {
+            Ok(block) => block,
+            Err(err) => {
+                tracing::warn!(target: TARGET_API, "Stream stopped at block {}: {:?}", block_height, err);
+                return None;
+            }
+        };
+        let block = if block.is_empty() { "null" } else { &block };
+        // Multi-line data has to be split into multiple `data` fields
+        let data: String = block
+            .lines()
+            .map(|line| format!("data: {}\n", line))
+            .collect();
+        let event = format!("id: {}\nevent: block\n{}\n", block_height, data);
+        Some((Ok(web::Bytes::from(event)), block_height + 1))
+    }
+
+    /// Handles a WebSocket subscription session.
+    ///
+    /// The first text message from the client has to be a `Subscription`. After that the server
+    /// sends the matching receipts of every block in order, starting from `from_block_height` or
+    /// from the last block. Blocks without matching receipts are not sent. On error, the server
+    /// sends an error message and closes the session.
+    ///
+    /// The messages are built by a separate task, so the client frames never cancel a block read
+    /// in progress, e.g. one holding an archive read attempt.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The WebSocket session to send messages to.
+    /// * `msg_stream` - The stream of messages from the client.
+    /// * `app_state` - The application state containing configuration and cache information.
+    async fn handle_ws_session(
+        mut session: actix_ws::Session,
+        mut msg_stream: actix_ws::MessageStream,
+        app_state: web::Data<AppState>,
+    ) {
+        let subscription = loop {
+            match msg_stream.recv().await {
+                Some(Ok(actix_ws::Message::Text(text))) => {
+                    match serde_json::from_str::<Subscription>(&text) {
+                        Ok(subscription) => break subscription,
+                        Err(err) => {
+                            close_ws_with_error(session, format!("Invalid subscription: {}", err))
+                                .await;
+                            return;
+                        }
+                    }
+                }
+                Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => {
+                    let _ = session.close(None).await;
+                    return;
+                }
+                Some(Ok(_)) => {}
+            }
+        };
+
+        tracing::debug!(target: TARGET_API, "New WebSocket subscription: {:?}", subscription);
+
+        let block_height = match subscription.from_block_height {
+            Some(block_height) => block_height,
+            None => match app_state
+                .cache
+                .get_last_block_height(app_state.chain_id, subscription.finality)
+                .await
+            {
+                Some(block_height) => block_height,
+                None => {
+                    close_ws_with_error(
+                        session,
+                        "The last block height is missing from the cache".to_string(),
+                    )
+                    .await;
+                    return;
+                }
+            },
+        };
+
+        let mut messages = spawn_ws_messages(block_height, subscription, app_state);
+
+        loop {
+            tokio::select! {
+                msg = msg_stream.recv() => match msg {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => {
+                        let _ = session.close(None).await;
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                },
+                message = messages.recv() => match message {
+                    Some(Ok(message)) => {
+                        if session.text(message).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Err(err)) => {
+                        close_ws_with_error(session, err).await;
+                        return;
+                    }
+                    None => {
+                        let _ = session.close(None).await;
+                        return;
+                    }
+                },
+            }
         }
     }
 
-    Ok(HttpResponse::Ok().json(json!({"status": "ok"})))
+    /// Spawns the task building the subscription messages, starting from the given block height.
+    ///
+    /// The task stops after an error or once the receiver is dropped, either before the next block
+    /// or while waiting for a new block. A block read in progress is never cancelled, see
+    /// `handle_ws_session`.
+    pub(crate) fn spawn_ws_messages(
+        mut block_height: BlockHeight,
+        subscription: Subscription,
+        app_state: web::Data<AppState>,
+    ) -> tokio::sync::mpsc::Receiver<Result<String, String>> {
+        let (sender, messages) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                // Blocks without matching receipts send nothing, so a send error can't be
+                // relied on to notice the session is gone
+                if sender.is_closed() {
+                    return;
+                }
+                let finality = subscription.finality;
+                let is_next_block = app_state
+                    .cache
+                    .last_known_block_height(app_state.chain_id, finality)
+                    .is_some_and(|last_block_height| {
+                        block_height > last_block_height
+                            && block_height <= last_block_height + MAX_WAIT_BLOCKS
+                    });
+                if is_next_block {
+                    // Unlike the block read, waiting for the block stops once the session is gone
+                    let res = tokio::select! {
+                        res = app_state.cache.wait_for_block(
+                            app_state.chain_id,
+                            block_height,
+                            finality,
+                            STREAM_KEEP_ALIVE_INTERVAL,
+                        ) => res,
+                        _ = sender.closed() => return,
+                    };
+                    match res {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        Err(err) => {
+                            let _ = sender.send(Err(ServiceError::from(err).to_string())).await;
+                            return;
+                        }
+                    }
+                }
+                let res = next_ws_message(block_height, &subscription, &app_state).await;
+                let is_err = res.is_err();
+                if let Some(res) = res.transpose() {
+                    if sender.send(res).await.is_err() {
+                        return;
+                    }
+                }
+                if is_err {
+                    return;
+                }
+                block_height += 1;
+            }
+        });
+        messages
+    }
+
+    /// Retrieves the block and builds the subscription message with the matching receipts.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_height` - The height of the block to retrieve.
+    /// * `subscription` - The subscription with the finality and the filter.
+    /// * `app_state` - The application state containing configuration and cache information.
+    ///
+    /// # Returns
+    ///
+    /// The message to send, `None` if nothing matched, or an error message.
+    async fn next_ws_message(
+        block_height: BlockHeight,
+        subscription: &Subscription,
+        app_state: &web::Data<AppState>,
+    ) -> Result<Option<String>, String> {
+        let finality = subscription.finality;
+        if let Some(response) = check_block_height_limits(block_height, app_state)
+            .or_else(|| check_archive_redirects(block_height, finality, app_state))
+        {
+            return Err(response_error_message(block_height, &response));
+        }
+
+        let chain_id = app_state.chain_id;
+        let block = loop {
+            let (block, last_block_height) = app_state
+                .cache
+                .get_block_and_last_block_height(chain_id, block_height, finality)
+                .await
+                .map_err(|err| ServiceError::from(err).to_string())?;
+            if let Some(block) = block {
+                break block;
+            }
+            // A recent block missing from the cache is either skipped or not cached yet
+            let Some(last_block_height) = last_block_height.filter(|&last_block_height| {
+                block_height <= last_block_height
+                    && block_height > last_block_height.saturating_sub(EXPECTED_CACHED_BLOCKS)
+            }) else {
+                match retrieve_block_from_cache_or_archive(
+                    block_height,
+                    finality,
+                    app_state,
+                    chain_id,
+                )
+                .await
+                .map_err(|err| err.to_string())?
+                {
+                    BlockOrResponse::Block(block) => break block,
+                    BlockOrResponse::ArchiveError(error) => {
+                        return Err(response_error_message(
+                            block_height,
+                            &archive_error_response(error),
+                        ))
+                    }
+                    BlockOrResponse::Response(response) => {
+                        return Err(response_error_message(block_height, &response))
+                    }
+                }
+            };
+            let is_skipped = is_missing_block_skipped(
+                app_state.cache.as_ref(),
+                chain_id,
+                block_height,
+                last_block_height,
+                finality,
+            )
+            .await
+            .map_err(|err| ServiceError::from(err).to_string())?;
+            if is_skipped == Some(true) {
+                break EncodedBlock::from_string(String::new());
+            }
+            // The later blocks don't tell yet, so wait for the next one before retrying
+            app_state
+                .cache
+                .wait_for_block(
+                    chain_id,
+                    last_block_height + 1,
+                    finality,
+                    STREAM_KEEP_ALIVE_INTERVAL,
+                )
+                .await
+                .map_err(|err| ServiceError::from(err).to_string())?;
+        };
+        if block.is_empty() {
+            return Ok(None);
+        }
+
+        let block_json: Value = block
+            .into_string()
+            .ok()
+            .and_then(|block| serde_json::from_str(&block).ok())
+            .ok_or_else(|| ServiceError::InternalDataError.to_string())?;
+        let shards = subscription.filter.filter_shards(&block_json);
+        if shards.is_empty() {
+            return Ok(None);
+        }
+        let header = &block_json["block"]["header"];
+        Ok(Some(
+            json!({
+                "type": "receipts",
+                "block_height": block_height,
+                "block_hash": header["hash"],
+                "block_timestamp": header["timestamp_nanosec"],
+                "shards": shards,
+            })
+            .to_string(),
+        ))
+    }
+
+    fn response_error_message(block_height: BlockHeight, response: &HttpResponse) -> String {
+        match header(response, header::LOCATION) {
+            Some(location) => format!(
+                "The block {} is served by a different url: {}",
+                block_height, location
+            ),
+            None => format!(
+                "The block {} can't be served: {}",
+                block_height,
+                response.status()
+            ),
+        }
+    }
+
+    async fn close_ws_with_error(mut session: actix_ws::Session, error: String) {
+        let _ = session
+            .text(json!({"type": "error", "error": error}).to_string())
+            .await;
+        let _ = session.close(None).await;
+    }
+
+    /// Looks up the block index on the blocking thread pool.
+    async fn lookup_index<T, F>(
+        app_state: &web::Data<AppState>,
+        f: F,
+    ) -> Result<Option<T>, ServiceError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&BlockIndex) -> Result<Option<T>, IndexError> + Send + 'static,
+    {
+        let block_index = app_state
+            .block_index
+            .clone()
+            .ok_or(ServiceError::IndexNotEnabled)?;
+        web::block(move || f(&block_index))
+            .await
+            .map_err(|_| ServiceError::IndexError("The index lookup failed".to_string()))?
+            .map_err(ServiceError::from)
+    }
+
+    /// Redirects to the location found in the index, preserving the query string.
+    fn index_redirect(
+        request: &HttpRequest,
+        location: Option<String>,
+        error: &str,
+        error_type: &str,
+    ) -> HttpResponse {
+        let Some(location) = location else {
+            return HttpResponse::NotFound().json(json!({
+                "error": error,
+                "type": error_type
+            }));
+        };
+        // Preserve the query string if any
+        let query_string = request.query_string();
+        let location = if query_string.is_empty() {
+            location
+        } else {
+            format!("{}?{}", location, query_string)
+        };
+        HttpResponse::Found()
+            .append_header((
+                header::CACHE_CONTROL,
+                format!("public, max-age={}", 24 * 60 * 60),
+            ))
+            .append_header((header::LOCATION, location))
+            .finish()
+    }
+
+    /// Retrieves a range of finalized blocks from the cache or archive.
+    ///
+    /// All blocks are first fetched from the cache in one round-trip. The missing blocks are read
+    /// from the archive files, so each archive file is decoded at most once per request.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_block_height` - The first block height of the range (inclusive).
+    /// * `to_block_height` - The last block height of the range (inclusive).
+    /// * `suffix` - The query string suffix to preserve on redirect.
+    /// * `app_state` - The application state containing configuration and cache information.
+    ///
+    /// # Returns
+    ///
+    /// The blocks in order of block height (empty for skipped blocks) with the last block height,
+    /// or a response.
+    async fn retrieve_blocks_from_cache_or_archive(
+        from_block_height: BlockHeight,
+        to_block_height: BlockHeight,
+        suffix: &str,
+        app_state: &web::Data<AppState>,
+    ) -> Result<BlocksOrResponse, ServiceError> {
+        let chain_id = app_state.chain_id;
+        let finality = Finality::Final;
+        let block_heights: Vec<BlockHeight> = (from_block_height..=to_block_height).collect();
+
+        // The blocks in memory are finalized, so the range doesn't need Redis if all of them are.
+        let memory_blocks: Option<Vec<EncodedBlock>> = block_heights
+            .iter()
+            .map(|block_height| app_state.memory_cache.get(*block_height))
+            .collect();
+        if let Some(memory_blocks) = memory_blocks {
+            return Ok(BlocksOrResponse::Blocks {
+                blocks: memory_blocks
+                    .into_iter()
+                    .map(EncodedBlock::into_string)
+                    .collect::<std::io::Result<_>>()
+                    .map_err(|_| ServiceError::InternalDataError)?,
+                last_block_height: None,
+            });
+        }
+
+        let (blocks, last_block_height) = app_state
+            .cache
+            .get_blocks_and_last_block_height(chain_id, &block_heights, finality)
+            .await?;
+        for (block_height, block) in block_heights.iter().zip(&blocks) {
+            if let Some(block) = block {
+                app_state.memory_cache.insert(*block_height, block.clone());
+            }
+        }
+        // An archive node doesn't need the last block height to read the archive files
+        let latest_block_height = if app_state.is_latest {
+            Some(last_block_height.ok_or_else(|| {
+                ServiceError::CacheError(
+                    "The last block height is missing from the cache".to_string(),
+                )
+            })?)
+        } else {
+            None
+        };
+
+        if latest_block_height.is_some_and(|last_block_height| to_block_height > last_block_height)
+        {
+            return Ok(BlocksOrResponse::Response(HttpResponse::NotFound().json(
+                json!({
+                    "error": "The block range is not finalized yet",
+                    "type": "BLOCK_DOES_NOT_EXIST"
+                }),
+            )));
+        }
+
+        let mut blocks: Vec<(BlockHeight, Option<String>)> = block_heights
+            .into_iter()
+            .zip(blocks)
+            .map(|(block_height, block)| {
+                Ok((
+                    block_height,
+                    block.map(EncodedBlock::into_string).transpose()?,
+                ))
+            })
+            .collect::<std::io::Result<_>>()
+            .map_err(|_| ServiceError::InternalDataError)?;
+        let missing_block_heights: Vec<BlockHeight> = blocks
+            .iter()
+            .filter(|(_, block)| block.is_none())
+            .map(|(block_height, _)| *block_height)
+            .collect();
+
+        if let Some(&last_missing_block_height) = missing_block_heights.last() {
+            if latest_block_height.is_some_and(|last_block_height| {
+                last_missing_block_height > last_block_height.saturating_sub(EXPECTED_CACHED_BLOCKS)
+            }) {
+                return Err(ServiceError::CacheError(
+                    "The block is not cached".to_string(),
+                ));
+            }
+
+            let Some(read_config) = app_state.read_config.as_ref() else {
+                // The server doesn't use archive files, redirect to the latest server with files.
+                let archive_config = app_state
+                    .archive_config
+                    .as_ref()
+                    .expect("Missing archive config without local files config");
+                return Ok(BlocksOrResponse::Response(
+                    HttpResponse::Found()
+                        .append_header((
+                            header::CACHE_CONTROL,
+                            format!("public, max-age={}", 24 * 60 * 60),
+                        ))
+                        .append_header((
+                            header::LOCATION,
+                            format!(
+                                "https://a{}.{}/v0/blocks/{}/{}{}",
+                                archive_config.archive_boundaries.len(),
+                                archive_config.domain_name,
+                                from_block_height,
+                                to_block_height,
+                                suffix
+                            ),
+                        ))
+                        .finish(),
+                ));
+            };
+
+            // Each archive file is read once, starting from its first block
+            let mut archive_start_heights: Vec<BlockHeight> = missing_block_heights
+                .iter()
+                .map(|block_height| {
+                    block_height / read_config.save_every_n * read_config.save_every_n
+                })
+                .collect();
+            archive_start_heights.dedup();
+
+            for archive_start_height in archive_start_heights {
+                if let Some(error) = fill_blocks_from_archive(
+                    app_state,
+                    read_config,
+                    archive_start_height,
+                    &mut blocks,
+                )
+                .await?
+                {
+                    return Ok(BlocksOrResponse::Response(archive_error_response(error)));
+                }
+            }
+        }
+
+        Ok(BlocksOrResponse::Blocks {
+            blocks: blocks
+                .into_iter()
+                .map(|(_, block)| block.unwrap_or_default())
+                .collect(),
+            last_block_height,
+        })
+    }
+
+    /// Fills the missing finalized blocks of the archive file starting at `archive_start_height`.
+    ///
+    /// Same as `handle_not_cached_block`, the archive file is only read if no other request is
+    /// reading it. Otherwise, the blocks are taken from the cache once the other request caches
+    /// them.
+    ///
+    /// # Returns
+    ///
+    /// The archive error if the archive file has unavailable blocks, or `None` if the blocks are
+    /// filled.
+    async fn fill_blocks_from_archive(
+        app_state: &web::Data<AppState>,
+        read_config: &ReadConfig,
+        archive_start_height: BlockHeight,
+        blocks: &mut [(BlockHeight, Option<String>)],
+    ) -> Result<Option<ArchiveError>, ServiceError> {
+        let chain_id = app_state.chain_id;
+        let finality = Finality::Final;
+        let archive_heights = archive_start_height..archive_start_height + read_config.save_every_n;
+        let archive_fn = archive_filename(read_config, chain_id, archive_start_height);
+        loop {
+            // The attempt only saves reading the archive file twice, so it's read if the attempt
+            // fails.
+            let should_read = match app_state
+                .cache
+                .acquire_archive_read_attempt(&archive_fn)
+                .await
+            {
+                Ok(should_read) => should_read,
+                Err(err) => {
+                    tracing::warn!(target: TARGET_API, "Failed to acquire the read attempt of {}: {:?}", archive_fn, err);
+                    true
+                }
+            };
+            if should_read {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let missing_block_heights: Vec<BlockHeight> = blocks
+                .iter()
+                .filter(|(block_height, block)| {
+                    block.is_none() && archive_heights.contains(block_height)
+                })
+                .map(|(block_height, _)| *block_height)
+                .collect();
+            let (cached_blocks, _) = app_state
+                .cache
+                .get_blocks_and_last_block_height(chain_id, &missing_block_heights, finality)
+                .await?;
+            for (missing_block_height, cached_block) in
+                missing_block_heights.iter().zip(cached_blocks)
+            {
+                let Some(cached_block) = cached_block else {
+                    continue;
+                };
+                if let Some((_, block)) = blocks
+                    .iter_mut()
+                    .find(|(block_height, _)| block_height == missing_block_height)
+                {
+                    *block = Some(
+                        cached_block
+                            .into_string()
+                            .map_err(|_| ServiceError::InternalDataError)?,
+                    );
+                }
+            }
+            if blocks.iter().all(|(block_height, block)| {
+                block.is_some() || !archive_heights.contains(block_height)
+            }) {
+                return Ok(None);
+            }
+        }
+
+        let archive_blocks = read_blocks(
+            read_config,
+            &app_state.archive_read_pool,
+            chain_id,
+            archive_start_height,
+        )
+        .await;
+        cache_archive_blocks(app_state, chain_id, finality, &archive_blocks);
+        for (block_height, block) in blocks.iter_mut() {
+            if block.is_some() {
+                continue;
+            }
+            let Some((_, archive_block)) = archive_blocks.iter().find(|(h, _)| h == block_height)
+            else {
+                continue;
+            };
+            if let ArchiveBlock::Unavailable(error) = archive_block {
+                // Let the next request retry reading the archive, since the block wasn't cached.
+                if let Err(err) = app_state
+                    .cache
+                    .release_archive_read_attempt(&archive_fn)
+                    .await
+                {
+                    tracing::warn!(target: TARGET_API, "Failed to release the read attempt of {}: {:?}", archive_fn, err);
+                }
+                return Ok(Some(*error));
+            }
+            *block = archive_block.cached();
+        }
+        Ok(None)
+    }
+}
+
+#[get("/health")]
+pub async fn health(app_state: web::Data<AppState>) -> Result<impl Responder, ServiceError> {
+    let archive_reads = app_state.archive_read_pool.stats();
+    let memory_cache = app_state.memory_cache.stats();
+    if !app_state.is_latest {
+        return Ok(HttpResponse::Ok().json(json!({
+            "status": "ok",
+            "archive_reads": archive_reads,
+            "memory_cache": memory_cache
+        })));
+    }
+    let chain_id = app_state.chain_id;
+    let finality = Finality::Final;
+    let block_height = app_state
+        .cache
+        .get_last_block_height(chain_id, finality)
+        .await
+        .ok_or_else(|| {
+            ServiceError::CacheError("The last block height is missing from the cache".to_string())
+        })?;
+    match app_state
+        .cache
+        .get_block_and_last_block_height(chain_id, block_height, finality)
+        .await?
+    {
+        (Some(block), _) => {
+            let block = block
+                .into_string()
+                .map_err(|_| ServiceError::CacheError("Failed to decode the block".to_string()))?;
+            let block: serde_json::Value = serde_json::from_str(&block)
+                .map_err(|_| ServiceError::CacheError("Failed to parse the block".to_string()))?;
+            let timestamp = block["block"]["header"]["timestamp_nanosec"]
+                .as_str()
+                .ok_or_else(|| {
+                    ServiceError::CacheError("The block is missing a timestamp".to_string())
+                })?;
+            let t_nano = timestamp.parse::<u128>().unwrap_or(0);
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            let sync_latency_ms = now.as_nanos().saturating_sub(t_nano) / 1_000_000;
+            if sync_latency_ms > app_state.max_healthy_latency_ms {
+                return Ok(HttpResponse::Ok().json(json!({
+                    "status": "unhealthy",
+                    "archive_reads": archive_reads,
+                    "memory_cache": memory_cache
+                })));
+            }
+        }
+        _ => {
+            return Err(ServiceError::CacheError(
+                "The block is not cached".to_string(),
+            ));
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "ok",
+        "archive_reads": archive_reads,
+        "memory_cache": memory_cache
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::InMemoryCache;
+    use crate::index::BlockIndex;
+    use crate::reader::{archive_filename, FsArchiveStore};
+    use crate::writer::{encode_archive, write_file_atomically};
+    use actix_web::body::MessageBody;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use std::sync::Arc;
+
+    const CHAIN_ID: ChainId = ChainId::Mainnet;
+    const LAST_BLOCK_HEIGHT: BlockHeight = 1000;
+    const SKIPPED_BLOCK_HEIGHT: BlockHeight = 998;
+
+    fn block(block_height: BlockHeight) -> String {
+        format!(r#"{{"block":{{"header":{{"height":{}}}}}}}"#, block_height)
+    }
+
+    /// The latest node without archive files, with the last blocks cached in memory.
+    async fn app_state() -> AppState {
+        let cache = InMemoryCache::new();
+        cache.set_last_block_height(CHAIN_ID, Finality::Final, LAST_BLOCK_HEIGHT);
+        let blocks = (LAST_BLOCK_HEIGHT - 5..=LAST_BLOCK_HEIGHT)
+            .map(|block_height| {
+                let block = if block_height == SKIPPED_BLOCK_HEIGHT {
+                    String::new()
+                } else {
+                    block(block_height)
+                };
+                (block_height, EncodedBlock::from_string(block))
+            })
+            .collect();
+        cache
+            .set_multiple_blocks(CHAIN_ID, Finality::Final, blocks)
+            .await
+            .unwrap();
+        AppState {
+            cache: Arc::new(cache),
+            read_config: None,
+            chain_id: CHAIN_ID,
+            genesis_block_height: 0,
+            is_latest: true,
+            is_fresh: true,
+            archive_config: None,
+            max_healthy_latency_ms: 10_000,
+            block_index: None,
+            cache_compression: Compression::None,
+            archive_read_pool: Arc::new(BlockingPool::new(1, 1, Duration::from_secs(10))),
+            memory_cache: Arc::new(MemoryBlockCache::new(0)),
+        }
+    }
+
+    /// Returns the status, the `Cache-Control` header and the body of the response.
+    async fn get(uri: &str) -> (StatusCode, String, String) {
+        get_with_state(app_state().await, uri).await
+    }
+
+    async fn get_with_state(app_state: AppState, uri: &str) -> (StatusCode, String, String) {
+        let response = call_with_state(app_state, test::TestRequest::get().uri(uri)).await;
+        let status = response.status();
+        let cache_control = response
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = test::read_body(response).await;
+        (
+            status,
+            cache_control,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    async fn call_with_state(
+        app_state: AppState,
+        request: test::TestRequest,
+    ) -> actix_web::dev::ServiceResponse {
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(app_state)).service(
+                web::scope("/v0")
+                    .service(v0::get_block)
+                    .service(v0::get_blocks)
+                    .service(v0::get_transaction)
+                    .service(v0::get_archive)
+                    .service(v0::get_block_by_hash)
+                    .service(v0::get_chunk_by_hash),
+            ),
+        )
+        .await;
+        test::call_service(&app, request.to_request()).await
+    }
+
+    #[actix_web::test]
+    async fn test_get_block() {
+        let (status, cache_control, body) = get(&format!("/v0/block/{}", LAST_BLOCK_HEIGHT)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cache_control, "public, max-age=31536000");
+        assert_eq!(body, block(LAST_BLOCK_HEIGHT));
+
+        let (status, _, body) = get(&format!("/v0/block/{}", LAST_BLOCK_HEIGHT + 100)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("BLOCK_DOES_NOT_EXIST"));
+    }
+
+    #[actix_web::test]
+    async fn test_get_skipped_block() {
+        let (status, cache_control, body) =
+            get(&format!("/v0/block/{}", SKIPPED_BLOCK_HEIGHT)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cache_control, "public, max-age=86400");
+        assert_eq!(body, "null");
+    }
+
+    #[actix_web::test]
+    async fn test_get_blocks() {
+        let (status, cache_control, body) = get(&format!(
+            "/v0/blocks/{}/{}",
+            SKIPPED_BLOCK_HEIGHT - 1,
+            SKIPPED_BLOCK_HEIGHT + 1
+        ))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            format!(
+                "[{},null,{}]",
+                block(SKIPPED_BLOCK_HEIGHT - 1),
+                block(SKIPPED_BLOCK_HEIGHT + 1)
+            )
+        );
+        // A range with a skipped block is cached for a day, same as the skipped block
+        assert_eq!(cache_control, "public, max-age=86400");
+
+        let (status, cache_control, body) = get(&format!(
+            "/v0/blocks/{}/{}",
+            SKIPPED_BLOCK_HEIGHT + 1,
+            LAST_BLOCK_HEIGHT
+        ))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            format!(
+                "[{},{}]",
+                block(SKIPPED_BLOCK_HEIGHT + 1),
+                block(LAST_BLOCK_HEIGHT)
+            )
+        );
+        assert_eq!(cache_control, "public, max-age=31536000");
+
+        let (status, _, body) = get("/v0/blocks/10/1").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, r#""Invalid argument""#);
+    }
+
+    #[actix_web::test]
+    async fn test_get_transaction() {
+        let block_json = json!({
+            "block": {"header": {"height": LAST_BLOCK_HEIGHT - 1}},
+            "shards": [
+                {
+                    "shard_id": 0,
+                    "chunk": {"transactions": [
+                        {"transaction": {"hash": "tx1"}, "outcome": {"id": "tx1"}},
+                    ]},
+                    "receipt_execution_outcomes": [{"tx_hash": "tx2", "receipt": {"receipt_id": "r2"}}],
+                },
+                {
+                    "shard_id": 1,
+                    "chunk": {"transactions": []},
+                    "receipt_execution_outcomes": [{"tx_hash": "tx1", "receipt": {"receipt_id": "r1"}}],
+                },
+            ],
+        });
+        let app_state = app_state().await;
+        app_state
+            .cache
+            .set_multiple_blocks(
+                CHAIN_ID,
+                Finality::Final,
+                vec![(
+                    LAST_BLOCK_HEIGHT - 1,
+                    EncodedBlock::from_string(block_json.to_string()),
+                )],
+            )
+            .await
+            .unwrap();
+        let uri = |tx_hash: &str| format!("/v0/block/{}/tx/{}", LAST_BLOCK_HEIGHT - 1, tx_hash);
+
+        // The transaction is in this block, and one of its receipts is executed here
+        let (status, _, body) = get_with_state(app_state.clone(), &uri("tx1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!({
+                "shard_id": 0,
+                "transaction": {"hash": "tx1"},
+                "outcome": {"id": "tx1"},
+                "receipt_execution_outcomes": [{"tx_hash": "tx1", "receipt": {"receipt_id": "r1"}}],
+            })
+        );
+
+        // Only a receipt of the transaction is executed here
+        let (status, _, body) = get_with_state(app_state.clone(), &uri("tx2")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!({
+                "shard_id": null,
+                "transaction": null,
+                "outcome": null,
+                "receipt_execution_outcomes": [{"tx_hash": "tx2", "receipt": {"receipt_id": "r2"}}],
+            })
+        );
+
+        let (status, _, body) = get_with_state(app_state.clone(), &uri("tx3")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "null");
+    }
+
+    #[actix_web::test]
+    async fn test_response_format() {
+        let format = |accept: &str| {
+            ResponseFormat::from_request(
+                &test::TestRequest::default()
+                    .insert_header((header::ACCEPT, accept))
+                    .to_http_request(),
+            )
+        };
+        assert_eq!(format("application/cbor"), ResponseFormat::Cbor);
+        assert_eq!(
+            format("application/json;q=0.1, application/cbor"),
+            ResponseFormat::Cbor
+        );
+        assert_eq!(
+            format("application/cbor;q=0.5, application/msgpack;q=0.8, */*;q=0.1"),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            format("application/msgpack, application/cbor"),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(format("application/cbor;q=0"), ResponseFormat::Json);
+        assert_eq!(format("text/html, */*"), ResponseFormat::Json);
+        assert_eq!(
+            ResponseFormat::from_request(&test::TestRequest::default().to_http_request()),
+            ResponseFormat::Json
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_ws_redirects_to_fresh_server() {
+        let app_state = AppState {
+            is_fresh: false,
+            archive_config: Some(ArchiveConfig {
+                archive_boundaries: vec![],
+                domain_name: "mainnet.neardata.xyz".to_string(),
+                archive_index: 0,
+            }),
+            ..app_state().await
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(app_state))
+                .service(web::scope("/v0").service(v0::get_ws)),
+        )
+        .await;
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get().uri("/v0/ws?apiKey=1").to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "https://mainnet.neardata.xyz/v0/ws?apiKey=1"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_stream_skipped_block() {
+        // Block 999 is missing from the cache, and the next block confirms it was skipped
+        let cache = InMemoryCache::new();
+        cache.set_last_block_height(CHAIN_ID, Finality::Final, LAST_BLOCK_HEIGHT);
+        let next_block = format!(
+            r#"{{"block":{{"header":{{"height":{},"prev_height":{}}}}}}}"#,
+            LAST_BLOCK_HEIGHT,
+            LAST_BLOCK_HEIGHT - 2
+        );
+        cache
+            .set_multiple_blocks(
+                CHAIN_ID,
+                Finality::Final,
+                vec![(LAST_BLOCK_HEIGHT, EncodedBlock::from_string(next_block))],
+            )
+            .await
+            .unwrap();
+        let app_state = AppState {
+            cache: Arc::new(cache),
+            ..app_state().await
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(app_state))
+                .service(web::scope("/v0").service(v0::get_stream)),
+        )
+        .await;
+        let request = test::TestRequest::get()
+            .uri("/v0/stream/final")
+            .insert_header(("Last-Event-ID", (LAST_BLOCK_HEIGHT - 2).to_string()))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = std::pin::pin!(response.into_body());
+        let event = std::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            format!(
+                "id: {}\nevent: block\ndata: null\n\n",
+                LAST_BLOCK_HEIGHT - 1
+            )
+        );
+    }
+
+    /// The archive node without Redis, so the last block height is unknown, serving the archive
+    /// file of blocks 100 to 104 with the skipped block 102 from a new temporary directory.
+    ///
+    /// # Returns
+    ///
+    /// The application state, the directory and the archive file.
+    async fn archive_app_state(name: &str) -> (AppState, std::path::PathBuf, Vec<u8>) {
+        let path =
+            std::env::temp_dir().join(format!("neardata-api-test-{}-{}", name, std::process::id()));
+        let read_config = ReadConfig {
+            store: Arc::new(FsArchiveStore::new(path.to_string_lossy().to_string())),
+            save_every_n: 5,
+        };
+        let blocks: Vec<(BlockHeight, Option<String>)> = (100..105)
+            .map(|block_height| {
+                (
+                    block_height,
+                    (block_height != 102).then(|| block(block_height)),
+                )
+            })
+            .collect();
+        let (data, _) = encode_archive(100, &blocks).unwrap();
+        write_file_atomically(
+            &path.join(archive_filename(&read_config, CHAIN_ID, 100)),
+            &data,
+        )
+        .unwrap();
+        let app_state = AppState {
+            cache: Arc::new(InMemoryCache::new()),
+            read_config: Some(read_config),
+            is_latest: false,
+            ..app_state().await
+        };
+        (app_state, path, data)
+    }
+
+    #[actix_web::test]
+    async fn test_get_block_from_archive_without_last_block_height() {
+        let (app_state, path, _) = archive_app_state("block").await;
+
+        let (status, _, body) = get_with_state(app_state.clone(), "/v0/block/101").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, block(101));
+
+        let (status, cache_control, body) = get_with_state(app_state, "/v0/blocks/101/103").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, format!("[{},null,{}]", block(101), block(103)));
+        assert_eq!(cache_control, "public, max-age=86400");
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_get_archive() {
+        let (app_state, path, data) = archive_app_state("archive").await;
+
+        let response = call_with_state(
+            app_state.clone(),
+            test::TestRequest::get().uri("/v0/archive/100"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        assert_eq!(
+            test::read_body(response).await,
+            format!(
+                "{}\n{}\nnull\n{}\n{}\n",
+                block(100),
+                block(101),
+                block(103),
+                block(104)
+            )
+        );
+
+        // The raw archive file
+        let response = call_with_state(
+            app_state.clone(),
+            test::TestRequest::get()
+                .uri("/v0/archive/100")
+                .insert_header((header::ACCEPT, "application/gzip")),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/gzip"
+        );
+        assert_eq!(test::read_body(response).await, data);
+
+        // Each archive is only served at its first block height
+        let response = call_with_state(
+            app_state.clone(),
+            test::TestRequest::get().uri("/v0/archive/102"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "/v0/archive/100"
+        );
+
+        let (status, _, body) = get_with_state(app_state, "/v0/archive/105").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("ARCHIVE_NOT_FOUND"));
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    /// The latest node with the block index in a new temporary file, indexing block 1000 with the
+    /// chunk of shard 3.
+    ///
+    /// # Returns
+    ///
+    /// The application state and the index file.
+    async fn index_app_state(name: &str) -> (AppState, std::path::PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("neardata-api-test-{}-{}", name, std::process::id()));
+        let block_index = BlockIndex::open(&path.to_string_lossy()).unwrap();
+        let block_json = json!({
+            "block": {"header": {"height": LAST_BLOCK_HEIGHT, "hash": "hash1000"}},
+            "shards": [{"shard_id": 3, "chunk": {"header": {"chunk_hash": "chunk1000"}}}],
+        });
+        block_index
+            .index_blocks(&[(LAST_BLOCK_HEIGHT, Some(block_json.to_string()))], None)
+            .unwrap();
+        let app_state = AppState {
+            block_index: Some(Arc::new(block_index)),
+            ..app_state().await
+        };
+        (app_state, path)
+    }
+
+    /// Returns the status, the `Location` header and the body of the response.
+    async fn get_redirect(app_state: AppState, uri: &str) -> (StatusCode, String, String) {
+        let response = call_with_state(app_state, test::TestRequest::get().uri(uri)).await;
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = test::read_body(response).await;
+        (status, location, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_get_block_by_hash() {
+        let (app_state, path) = index_app_state("block-hash").await;
+
+        let (status, location, _) =
+            get_redirect(app_state.clone(), "/v0/block_hash/hash1000").await;
+        assert_eq!(status, StatusCode::FOUND);
+        assert_eq!(location, "/v0/block/1000");
+
+        // The trailing path and the query string are preserved
+        let (status, location, _) = get_redirect(
+            app_state.clone(),
+            "/v0/block_hash/hash1000/headers?fields=hash",
+        )
+        .await;
+        assert_eq!(status, StatusCode::FOUND);
+        assert_eq!(location, "/v0/block/1000/headers?fields=hash");
+
+        let (status, _, body) = get_redirect(app_state.clone(), "/v0/block_hash/hash999").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("BLOCK_HASH_NOT_FOUND"));
+
+        let app_state = AppState {
+            block_index: None,
+            ..app_state
+        };
+        let (status, _, body) = get_redirect(app_state, "/v0/block_hash/hash1000").await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert!(body.contains("INDEX_NOT_ENABLED"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_get_chunk_by_hash() {
+        let (app_state, path) = index_app_state("chunk").await;
+
+        let (status, location, _) = get_redirect(app_state.clone(), "/v0/chunk/chunk1000").await;
+        assert_eq!(status, StatusCode::FOUND);
+        assert_eq!(location, "/v0/block/1000/chunk/3");
+
+        let (status, _, body) = get_redirect(app_state.clone(), "/v0/chunk/chunk999").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("CHUNK_NOT_FOUND"));
+
+        let app_state = AppState {
+            block_index: None,
+            ..app_state
+        };
+        let (status, _, body) = get_redirect(app_state, "/v0/chunk/chunk1000").await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert!(body.contains("INDEX_NOT_ENABLED"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A block with one receipt execution outcome per receiver in shard 0.
+    fn block_with_receipts(block_height: BlockHeight, receiver_ids: &[&str]) -> String {
+        let outcomes: Vec<Value> = receiver_ids
+            .iter()
+            .map(|receiver_id| json!({"receipt": {"receiver_id": receiver_id}}))
+            .collect();
+        json!({
+            "block": {"header": {
+                "height": block_height,
+                "prev_height": block_height - 1,
+                "hash": format!("hash{}", block_height),
+                "timestamp_nanosec": "1",
+            }},
+            "shards": [{"shard_id": 0, "receipt_execution_outcomes": outcomes}],
+        })
+        .to_string()
+    }
+
+    /// The latest node with blocks 995 to 1000 cached, except the skipped block 998.
+    async fn ws_app_state() -> AppState {
+        let cache = InMemoryCache::new();
+        cache.set_last_block_height(CHAIN_ID, Finality::Final, LAST_BLOCK_HEIGHT);
+        let blocks = (LAST_BLOCK_HEIGHT - 5..=LAST_BLOCK_HEIGHT)
+            .filter(|&block_height| block_height != SKIPPED_BLOCK_HEIGHT)
+            .map(|block_height| {
+                let receiver_id = if block_height % 2 == 0 {
+                    "a.near"
+                } else {
+                    "b.near"
+                };
+                let mut block_json: Value =
+                    serde_json::from_str(&block_with_receipts(block_height, &[receiver_id]))
+                        .unwrap();
+                if block_height == SKIPPED_BLOCK_HEIGHT + 1 {
+                    block_json["block"]["header"]["prev_height"] = json!(SKIPPED_BLOCK_HEIGHT - 1);
+                }
+                (
+                    block_height,
+                    EncodedBlock::from_string(block_json.to_string()),
+                )
+            })
+            .collect();
+        cache
+            .set_multiple_blocks(CHAIN_ID, Finality::Final, blocks)
+            .await
+            .unwrap();
+        AppState {
+            cache: Arc::new(cache),
+            ..app_state().await
+        }
+    }
+
+    /// Starts the server and opens a WebSocket connection with the subscription.
+    async fn ws_subscribe(app_state: AppState, subscription: Value) -> tokio::net::TcpStream {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app_state = web::Data::new(app_state);
+        let server = actix_web::HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .service(web::scope("/v0").service(v0::get_ws))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_web::rt::spawn(server);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET /v0/ws HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            addr
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = vec![];
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        assert!(response.starts_with(b"HTTP/1.1 101"));
+
+        // The client frames have to be masked, and the zero mask leaves the payload as is
+        let text = subscription.to_string();
+        let mut frame = vec![0x81];
+        if text.len() < 126 {
+            frame.push(0x80 | text.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend((text.len() as u16).to_be_bytes());
+        }
+        frame.extend([0; 4]);
+        frame.extend(text.as_bytes());
+        stream.write_all(&frame).await.unwrap();
+        stream
+    }
+
+    /// Reads the next text message from the server, or `None` if there is none within a second.
+    async fn ws_recv(stream: &mut tokio::net::TcpStream) -> Option<Value> {
+        use tokio::io::AsyncReadExt;
+
+        let read = async {
+            let opcode = stream.read_u8().await.unwrap() & 0x0f;
+            let len = match stream.read_u8().await.unwrap() & 0x7f {
+                126 => stream.read_u16().await.unwrap() as usize,
+                127 => stream.read_u64().await.unwrap() as usize,
+                len => len as usize,
+            };
+            let mut payload = vec![0; len];
+            stream.read_exact(&mut payload).await.unwrap();
+            assert_eq!(opcode, 1, "Not a text frame");
+            serde_json::from_slice(&payload).unwrap()
+        };
+        tokio::time::timeout(Duration::from_secs(1), read)
+            .await
+            .ok()
+    }
+
+    #[actix_web::test]
+    async fn test_ws_subscription() {
+        let mut stream = ws_subscribe(
+            ws_app_state().await,
+            json!({
+                "from_block_height": LAST_BLOCK_HEIGHT - 4,
+                "filter": {"receiver_ids": ["b.near"]},
+            }),
+        )
+        .await;
+        for block_height in [LAST_BLOCK_HEIGHT - 3, LAST_BLOCK_HEIGHT - 1] {
+            let message = ws_recv(&mut stream).await.unwrap();
+            assert_eq!(message["type"], "receipts");
+            assert_eq!(message["block_height"], block_height);
+            assert_eq!(message["block_hash"], format!("hash{}", block_height));
+            assert_eq!(
+                message["shards"],
+                json!([{
+                    "shard_id": 0,
+                    "receipt_execution_outcomes": [{"receipt": {"receiver_id": "b.near"}}],
+                }])
+            );
+        }
+        assert_eq!(ws_recv(&mut stream).await, None);
+    }
+
+    #[actix_web::test]
+    async fn test_ws_skipped_block() {
+        // Block 998 is missing from the cache, and the next block confirms it was skipped
+        let mut stream = ws_subscribe(
+            ws_app_state().await,
+            json!({"from_block_height": SKIPPED_BLOCK_HEIGHT - 1}),
+        )
+        .await;
+        for block_height in [
+            SKIPPED_BLOCK_HEIGHT - 1,
+            SKIPPED_BLOCK_HEIGHT + 1,
+            LAST_BLOCK_HEIGHT,
+        ] {
+            let message = ws_recv(&mut stream).await.unwrap();
+            assert_eq!(message["block_height"], block_height);
+        }
+        assert_eq!(ws_recv(&mut stream).await, None);
+    }
+
+    #[actix_web::test]
+    async fn test_ws_messages_stop_after_disconnect() {
+        let app_state = web::Data::new(ws_app_state().await);
+        let subscription =
+            serde_json::from_value(json!({"filter": {"receiver_ids": ["c.near"]}})).unwrap();
+        // Nothing matches, and the task ends up waiting for the next block
+        let messages =
+            v0::spawn_ws_messages(LAST_BLOCK_HEIGHT - 5, subscription, app_state.clone());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(Arc::strong_count(&app_state.clone().into_inner()), 3);
+
+        drop(messages);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while Arc::strong_count(&app_state.clone().into_inner()) > 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The task didn't stop after the receiver was dropped");
+    }
 }
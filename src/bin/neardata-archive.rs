@@ -0,0 +1,625 @@
+use dotenv::dotenv;
+use serde_json::value::RawValue;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use neardata_server::reader::{
+    archive_blocks, archive_filename, read_archive_entries, seek_index_filename, ArchiveBlock,
+    FsArchiveStore,
+};
+use neardata_server::seek_index::SeekIndex;
+use neardata_server::types::{BlockHeight, ChainId};
+use neardata_server::writer::{encode_archive, write_file_atomically};
+use neardata_server::ReadConfig;
+use tracing_subscriber::EnvFilter;
+
+/// The number of blocks fetched with one `/v0/blocks` request during the repair.
+const REPAIR_RANGE: BlockHeight = 100;
+
+const USAGE: &str = "Usage: neardata-archive verify [--from <block_height>] [--to <block_height>] [--repair-from <url>]
+
+Verifies the archive files in READ_PATH for CHAIN_ID with SAVE_EVERY_N blocks per file.
+
+Options:
+  --from <block_height>  Only verify the archive files starting at or after this block height
+  --to <block_height>    Only verify the archive files up to this block height
+  --repair-from <url>    Rewrite the broken archive files with the blocks from a neardata server,
+                         e.g. https://mainnet.neardata.xyz";
+
+struct VerifyArgs {
+    from_block_height: BlockHeight,
+    to_block_height: BlockHeight,
+    repair_from: Option<String>,
+}
+
+impl VerifyArgs {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut verify_args = VerifyArgs {
+            from_block_height: 0,
+            to_block_height: BlockHeight::MAX,
+            repair_from: None,
+        };
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--from" => {
+                    verify_args.from_block_height = value
+                        .parse()
+                        .map_err(|_| format!("Invalid --from: {}", value))?
+                }
+                "--to" => {
+                    verify_args.to_block_height = value
+                        .parse()
+                        .map_err(|_| format!("Invalid --to: {}", value))?
+                }
+                "--repair-from" => {
+                    verify_args.repair_from = Some(value.trim_end_matches('/').to_string())
+                }
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+        }
+        Ok(verify_args)
+    }
+}
+
+/// The last block of the chain seen by the verifier.
+struct ChainTip {
+    block_height: BlockHeight,
+    block_hash: String,
+}
+
+/// Verifies the archive files in order of block height.
+///
+/// Every archive file is checked for unreadable entries, invalid JSON and blocks at wrong heights.
+/// The blocks are chained by `prev_height` and `prev_hash` across the archive files, so a block
+/// missing from an archive file is distinguished from a block skipped on chain.
+struct Verifier {
+    path: String,
+    chain_id: ChainId,
+    read_config: ReadConfig,
+    chain_tip: Option<ChainTip>,
+    issues: usize,
+    /// The first block heights of the archive files to repair.
+    broken_archives: BTreeSet<BlockHeight>,
+}
+
+impl Verifier {
+    fn new(path: String, chain_id: ChainId, read_config: ReadConfig) -> Self {
+        Self {
+            path,
+            chain_id,
+            read_config,
+            chain_tip: None,
+            issues: 0,
+            broken_archives: BTreeSet::new(),
+        }
+    }
+
+    fn report(&mut self, starting_block: BlockHeight, message: String) {
+        println!("{}: {}", starting_block, message);
+        self.issues += 1;
+        self.broken_archives.insert(starting_block);
+    }
+
+    fn starting_block(&self, block_height: BlockHeight) -> BlockHeight {
+        block_height / self.read_config.save_every_n * self.read_config.save_every_n
+    }
+
+    fn archive_path(&self, starting_block: BlockHeight) -> PathBuf {
+        Path::new(&self.path).join(archive_filename(
+            &self.read_config,
+            self.chain_id,
+            starting_block,
+        ))
+    }
+
+    fn seek_index_path(&self, starting_block: BlockHeight) -> PathBuf {
+        Path::new(&self.path).join(seek_index_filename(
+            &self.read_config,
+            self.chain_id,
+            starting_block,
+        ))
+    }
+
+    /// Returns the first block heights of all archive files of the chain, in order.
+    fn find_archives(&mut self) -> std::io::Result<Vec<BlockHeight>> {
+        let mut files = vec![];
+        collect_archive_files(
+            &Path::new(&self.path).join(self.chain_id.to_string()),
+            &mut files,
+        )?;
+        let mut archives = vec![];
+        for file in files {
+            let starting_block = file
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<BlockHeight>().ok());
+            match starting_block {
+                Some(starting_block)
+                    if self.starting_block(starting_block) == starting_block
+                        && self.archive_path(starting_block) == file =>
+                {
+                    archives.push(starting_block)
+                }
+                _ => {
+                    println!("{}: unexpected archive file", file.display());
+                    self.issues += 1;
+                }
+            }
+        }
+        archives.sort();
+        Ok(archives)
+    }
+
+    fn verify(&mut self, from_block_height: BlockHeight, to_block_height: BlockHeight) {
+        let archives = match self.find_archives() {
+            Ok(archives) => archives,
+            Err(e) => {
+                println!("Failed to list the archive files: {:?}", e);
+                self.issues += 1;
+                return;
+            }
+        };
+        let archives: Vec<BlockHeight> = archives
+            .into_iter()
+            .filter(|h| *h + self.read_config.save_every_n > from_block_height)
+            .filter(|h| *h <= to_block_height)
+            .collect();
+        let mut expected_starting_block = archives.first().copied();
+        for starting_block in archives {
+            while let Some(missing_starting_block) =
+                expected_starting_block.filter(|h| *h < starting_block)
+            {
+                self.report(
+                    missing_starting_block,
+                    "the archive file is missing".to_string(),
+                );
+                expected_starting_block =
+                    Some(missing_starting_block + self.read_config.save_every_n);
+            }
+            self.verify_archive(starting_block);
+            expected_starting_block = Some(starting_block + self.read_config.save_every_n);
+        }
+    }
+
+    fn verify_archive(&mut self, starting_block: BlockHeight) {
+        let save_every_n = self.read_config.save_every_n;
+        let data = match std::fs::read(self.archive_path(starting_block)) {
+            Ok(data) => data,
+            Err(e) => {
+                self.report(
+                    starting_block,
+                    format!("failed to read the archive file: {}", e),
+                );
+                return;
+            }
+        };
+        let entries = match read_archive_entries(&data) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.report(starting_block, format!("failed to read the archive: {}", e));
+                return;
+            }
+        };
+
+        let mut blocks = HashMap::new();
+        for entry in entries {
+            let (name, content) = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.report(starting_block, format!("unreadable entry: {}", e));
+                    continue;
+                }
+            };
+            let block_height = name
+                .strip_suffix(".json")
+                .and_then(|h| h.parse::<BlockHeight>().ok())
+                .filter(|h| self.starting_block(*h) == starting_block);
+            let Some(block_height) = block_height else {
+                self.report(starting_block, format!("unexpected entry {}", name));
+                continue;
+            };
+            blocks.insert(block_height, content);
+        }
+
+        self.verify_seek_index(starting_block, &data, &blocks);
+
+        for block_height in starting_block..starting_block + save_every_n {
+            let Some(block) = blocks.get(&block_height) else {
+                continue;
+            };
+            let Ok(block_json) = serde_json::from_str::<Value>(block) else {
+                self.report(
+                    starting_block,
+                    format!("block {}: invalid JSON", block_height),
+                );
+                continue;
+            };
+            let header = &block_json["block"]["header"];
+            if header["height"].as_u64() != Some(block_height) {
+                self.report(
+                    starting_block,
+                    format!(
+                        "block {}: unexpected height {}",
+                        block_height, header["height"]
+                    ),
+                );
+                continue;
+            }
+            let (Some(prev_height), Some(prev_hash), Some(block_hash)) = (
+                header["prev_height"].as_u64(),
+                header["prev_hash"].as_str(),
+                header["hash"].as_str(),
+            ) else {
+                self.report(
+                    starting_block,
+                    format!("block {}: missing header fields", block_height),
+                );
+                continue;
+            };
+            if let Some(chain_tip) = &self.chain_tip {
+                let chain_tip_height = chain_tip.block_height;
+                if prev_height > chain_tip_height {
+                    // The previous block is missing from its archive file
+                    self.report(
+                        self.starting_block(prev_height),
+                        format!(
+                            "block {}: missing, referenced by block {}",
+                            prev_height, block_height
+                        ),
+                    );
+                } else if prev_height < chain_tip_height {
+                    self.report(
+                        self.starting_block(chain_tip_height),
+                        format!(
+                            "block {}: not in the chain, block {} follows block {}",
+                            chain_tip_height, block_height, prev_height
+                        ),
+                    );
+                } else if prev_hash != chain_tip.block_hash {
+                    self.report(
+                        starting_block,
+                        format!(
+                            "block {}: prev_hash {} doesn't match the hash {} of block {}",
+                            block_height, prev_hash, chain_tip.block_hash, prev_height
+                        ),
+                    );
+                }
+            }
+            self.chain_tip = Some(ChainTip {
+                block_height,
+                block_hash: block_hash.to_string(),
+            });
+        }
+    }
+
+    /// Checks that the seek index, if any, points to the blocks of the archive file and marks
+    /// all other blocks as missing.
+    fn verify_seek_index(
+        &mut self,
+        starting_block: BlockHeight,
+        data: &[u8],
+        blocks: &HashMap<BlockHeight, String>,
+    ) {
+        let save_every_n = self.read_config.save_every_n;
+        let seek_index = match std::fs::read(self.seek_index_path(starting_block)) {
+            Ok(seek_index) => SeekIndex::decode(&seek_index),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => Err(e),
+        };
+        let seek_index = match seek_index {
+            Ok(seek_index)
+                if seek_index.starting_block == starting_block
+                    && seek_index.entries.len() as u64 == save_every_n =>
+            {
+                seek_index
+            }
+            Ok(_) => {
+                self.report(
+                    starting_block,
+                    "the seek index doesn't match the archive file".to_string(),
+                );
+                return;
+            }
+            Err(e) => {
+                self.report(starting_block, format!("invalid seek index: {}", e));
+                return;
+            }
+        };
+        for block_height in starting_block..starting_block + save_every_n {
+            let indexed_block = seek_index.get(block_height).map(|(offset, length)| {
+                data.get(offset as usize..(offset + length) as usize)
+                    .and_then(|member| {
+                        archive_blocks(save_every_n, starting_block, member)
+                            .into_iter()
+                            .find(|(h, _)| *h == block_height)
+                            .and_then(|(_, block)| match block {
+                                ArchiveBlock::Block(block) => Some(block),
+                                _ => None,
+                            })
+                    })
+            });
+            let matches = match (indexed_block, blocks.get(&block_height)) {
+                (None, None) => true,
+                (Some(indexed_block), Some(block)) => indexed_block.as_ref() == Some(block),
+                _ => false,
+            };
+            if !matches {
+                self.report(
+                    starting_block,
+                    format!(
+                        "block {}: the seek index doesn't match the archive",
+                        block_height
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Rewrites the broken archive files with the blocks from a neardata server. The blocks are
+    /// written exactly as served, without reformatting the JSON.
+    async fn repair(&mut self, url: &str) -> Result<(), String> {
+        let client = reqwest::Client::new();
+        let save_every_n = self.read_config.save_every_n;
+        for starting_block in std::mem::take(&mut self.broken_archives) {
+            let mut blocks = Vec::with_capacity(save_every_n as usize);
+            for from_block_height in
+                (starting_block..starting_block + save_every_n).step_by(REPAIR_RANGE as usize)
+            {
+                let to_block_height =
+                    (from_block_height + REPAIR_RANGE).min(starting_block + save_every_n) - 1;
+                let range: Vec<Option<Box<RawValue>>> = client
+                    .get(format!(
+                        "{}/v0/blocks/{}/{}",
+                        url, from_block_height, to_block_height
+                    ))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| format!("{}: failed to fetch the blocks: {}", starting_block, e))?
+                    .json()
+                    .await
+                    .map_err(|e| {
+                        format!("{}: failed to parse the blocks: {}", starting_block, e)
+                    })?;
+                if range.len() as u64 != to_block_height - from_block_height + 1 {
+                    return Err(format!("{}: unexpected number of blocks", starting_block));
+                }
+                blocks.extend((from_block_height..=to_block_height).zip(range).map(
+                    |(block_height, block)| {
+                        (block_height, block.map(|block| block.get().to_string()))
+                    },
+                ));
+            }
+
+            let archive_path = self.archive_path(starting_block);
+            let seek_index_path = self.seek_index_path(starting_block);
+            tokio::task::spawn_blocking(move || {
+                let (data, seek_index) = encode_archive(starting_block, &blocks)?;
+                write_file_atomically(&archive_path, &data)?;
+                write_file_atomically(&seek_index_path, &seek_index.encode())
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| {
+                format!(
+                    "{}: failed to write the archive file: {}",
+                    starting_block, e
+                )
+            })?;
+            println!("{}: repaired", starting_block);
+        }
+        Ok(())
+    }
+}
+
+fn collect_archive_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if is_hidden {
+            continue;
+        }
+        if path.is_dir() {
+            collect_archive_files(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension == "tgz") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    #[allow(deprecated)]
+    openssl_probe::init_ssl_cert_env_vars();
+    dotenv().ok();
+
+    tracing_subscriber::fmt::Subscriber::builder()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut args = env::args().skip(1);
+    if args.next().as_deref() != Some("verify") {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+    let args = VerifyArgs::parse(args).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+
+    let chain_id = ChainId::try_from(env::var("CHAIN_ID").expect("Missing CHAIN_ID env var"))
+        .expect("Failed to parse CHAIN_ID");
+    let path = env::var("READ_PATH").expect("Missing READ_PATH env var");
+    let read_config = ReadConfig {
+        store: Arc::new(FsArchiveStore::new(path.clone())),
+        save_every_n: env::var("SAVE_EVERY_N")
+            .expect("Missing SAVE_EVERY_N env var")
+            .parse()
+            .expect("Failed to parse SAVE_EVERY_N"),
+    };
+
+    let mut verifier = Verifier::new(path.clone(), chain_id, read_config.clone());
+    verifier.verify(args.from_block_height, args.to_block_height);
+    println!("Found {} issues", verifier.issues);
+
+    if let Some(url) = args
+        .repair_from
+        .as_ref()
+        .filter(|_| !verifier.broken_archives.is_empty())
+    {
+        if let Err(e) = verifier.repair(url).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        // The served blocks may be broken as well, so the repaired range is verified again
+        verifier = Verifier::new(path, chain_id, read_config);
+        verifier.verify(args.from_block_height, args.to_block_height);
+        println!("Found {} issues after the repair", verifier.issues);
+    }
+    if verifier.issues > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAVE_EVERY_N: BlockHeight = 5;
+
+    fn block(block_height: BlockHeight, prev_height: BlockHeight) -> String {
+        format!(
+            r#"{{"block":{{"header":{{"height":{},"prev_height":{},"prev_hash":"hash{}","hash":"hash{}"}}}}}}"#,
+            block_height, prev_height, prev_height, block_height
+        )
+    }
+
+    /// Blocks 100 to 109 chained by height, except block 102 skipped on chain.
+    fn chain() -> Vec<(BlockHeight, Option<String>)> {
+        (100..110)
+            .map(|block_height| match block_height {
+                102 => (block_height, None),
+                103 => (block_height, Some(block(103, 101))),
+                _ => (block_height, Some(block(block_height, block_height - 1))),
+            })
+            .collect()
+    }
+
+    /// Writes the blocks as seekable archive files to a new directory and returns its verifier.
+    fn verifier(name: &str, blocks: &[(BlockHeight, Option<String>)]) -> Verifier {
+        let path = std::env::temp_dir().join(format!(
+            "neardata-archive-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        let verifier = Verifier::new(
+            path.to_string_lossy().to_string(),
+            ChainId::Mainnet,
+            ReadConfig {
+                store: Arc::new(FsArchiveStore::new(path.to_string_lossy().to_string())),
+                save_every_n: SAVE_EVERY_N,
+            },
+        );
+        for archive_blocks in blocks.chunks(SAVE_EVERY_N as usize) {
+            let starting_block = archive_blocks[0].0;
+            let (data, seek_index) = encode_archive(starting_block, archive_blocks).unwrap();
+            write_file_atomically(&verifier.archive_path(starting_block), &data).unwrap();
+            write_file_atomically(
+                &verifier.seek_index_path(starting_block),
+                &seek_index.encode(),
+            )
+            .unwrap();
+        }
+        verifier
+    }
+
+    /// Verifies all archive files and returns the first block heights of the broken ones.
+    fn verify(mut verifier: Verifier) -> Vec<BlockHeight> {
+        verifier.verify(0, BlockHeight::MAX);
+        std::fs::remove_dir_all(&verifier.path).unwrap();
+        assert_eq!(verifier.issues > 0, !verifier.broken_archives.is_empty());
+        verifier.broken_archives.into_iter().collect()
+    }
+
+    #[test]
+    fn test_verify_skipped_block() {
+        assert_eq!(
+            verify(verifier("skipped", &chain())),
+            Vec::<BlockHeight>::new()
+        );
+    }
+
+    #[test]
+    fn test_verify_gap() {
+        // Block 106 is missing from its archive file, but block 107 follows it
+        let mut blocks = chain();
+        blocks[6].1 = None;
+        assert_eq!(verify(verifier("gap", &blocks)), vec![105]);
+    }
+
+    #[test]
+    fn test_verify_not_in_chain() {
+        // Block 104 follows block 101, so block 103 is not in the chain
+        let mut blocks = chain();
+        blocks[4].1 = Some(block(104, 101));
+        assert_eq!(verify(verifier("not-in-chain", &blocks)), vec![100]);
+    }
+
+    #[test]
+    fn test_verify_prev_hash_mismatch() {
+        // The chain is checked across the archive files
+        let mut blocks = chain();
+        blocks[5].1 =
+            Some(block(105, 104).replace("\"prev_hash\":\"hash104\"", "\"prev_hash\":\"x\""));
+        assert_eq!(verify(verifier("prev-hash", &blocks)), vec![105]);
+    }
+
+    #[test]
+    fn test_verify_missing_archive_file() {
+        let verifier = verifier("missing-file", &chain());
+        std::fs::remove_file(verifier.archive_path(100 + SAVE_EVERY_N)).unwrap();
+        let blocks: Vec<_> = (110..115)
+            .map(|block_height| (block_height, Some(block(block_height, block_height - 1))))
+            .collect();
+        let (data, _) = encode_archive(110, &blocks).unwrap();
+        write_file_atomically(&verifier.archive_path(110), &data).unwrap();
+        // Block 110 references block 109 of the missing file as well
+        assert_eq!(verify(verifier), vec![105]);
+    }
+
+    #[test]
+    fn test_verify_seek_index() {
+        let verifier = verifier("seek-index", &chain());
+        // The entries of blocks 100 and 101 are swapped
+        let seek_index_path = verifier.seek_index_path(100);
+        let mut seek_index = SeekIndex::decode(&std::fs::read(&seek_index_path).unwrap()).unwrap();
+        seek_index.entries.swap(0, 1);
+        write_file_atomically(&seek_index_path, &seek_index.encode()).unwrap();
+        // The seek index of another archive file
+        let seek_index_path = verifier.seek_index_path(105);
+        let mut seek_index = SeekIndex::decode(&std::fs::read(&seek_index_path).unwrap()).unwrap();
+        seek_index.starting_block = 100;
+        write_file_atomically(&seek_index_path, &seek_index.encode()).unwrap();
+
+        let mut verifier = verifier;
+        verifier.verify(0, BlockHeight::MAX);
+        std::fs::remove_dir_all(&verifier.path).unwrap();
+        assert_eq!(verifier.issues, 3);
+        assert_eq!(
+            verifier.broken_archives.into_iter().collect::<Vec<_>>(),
+            vec![100, 105]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use dotenv::dotenv;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use neardata_server::cache::{is_missing_block_skipped, BlockCache, RedisCache};
+use neardata_server::reader::{archive_filename, seek_index_filename, FsArchiveStore};
+use neardata_server::types::{BlockHeight, ChainId, Finality};
+use neardata_server::writer::{encode_archive, write_file_atomically};
+use neardata_server::ReadConfig;
+use tracing_subscriber::EnvFilter;
+
+const TARGET: &str = "archiver";
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// The archiver resumes from the last pending block only while the next block is expected to be
+/// cached.
+const MAX_LAG_BLOCKS: BlockHeight = 10;
+const PENDING_DIR: &str = ".pending";
+const LAST_BLOCK_FILE: &str = "last_block";
+
+/// Follows the finalized blocks in Redis and writes an archive file once every `SAVE_EVERY_N`
+/// blocks are final.
+///
+/// The blocks of the current archive file are kept in the pending directory until the archive file
+/// is complete, so the archiver can resume after a restart. An archive file is only written if all
+/// its blocks were observed, otherwise it's skipped.
+struct Archiver {
+    cache: RedisCache,
+    chain_id: ChainId,
+    path: String,
+    read_config: ReadConfig,
+    pending_path: PathBuf,
+}
+
+impl Archiver {
+    async fn run(&self) {
+        let finality = Finality::Final;
+        let mut block_height = self.start_block_height().await;
+        tracing::info!(target: TARGET, "Archiving blocks from {}", block_height);
+
+        loop {
+            let res = self
+                .cache
+                .get_block_and_last_block_height(self.chain_id, block_height, finality)
+                .await;
+            let block = match res {
+                // Skipped blocks are stored as empty values and are left out of the archive
+                Ok((Some(block), _)) if block.is_empty() => None,
+                Ok((Some(block), _)) => match block.into_string() {
+                    Ok(block) => Some(block),
+                    Err(e) => {
+                        tracing::error!(target: TARGET, "Failed to decode block {}: {:?}", block_height, e);
+                        tokio::time::sleep(WAIT_TIMEOUT).await;
+                        continue;
+                    }
+                },
+                Ok((None, Some(last_block_height))) if block_height <= last_block_height => {
+                    let res = is_missing_block_skipped(
+                        &self.cache,
+                        self.chain_id,
+                        block_height,
+                        last_block_height,
+                        finality,
+                    )
+                    .await;
+                    match res {
+                        Ok(Some(true)) => None,
+                        Ok(Some(false)) => {
+                            tracing::error!(target: TARGET, "Block {} has expired from the cache, skipping its archive", block_height);
+                            self.clear_pending().await;
+                            block_height = self.next_archive_start(last_block_height);
+                            continue;
+                        }
+                        Ok(None) => {
+                            // No later block tells yet whether this block was skipped
+                            tokio::time::sleep(WAIT_TIMEOUT).await;
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::warn!(target: TARGET, "Failed to check whether block {} was skipped: {:?}", block_height, e);
+                            tokio::time::sleep(WAIT_TIMEOUT).await;
+                            continue;
+                        }
+                    }
+                }
+                Ok((None, Some(_))) => {
+                    if let Err(e) = self
+                        .cache
+                        .wait_for_block(self.chain_id, block_height, finality, WAIT_TIMEOUT)
+                        .await
+                    {
+                        tracing::warn!(target: TARGET, "Error waiting for block {}: {:?}", block_height, e);
+                        tokio::time::sleep(WAIT_TIMEOUT).await;
+                    }
+                    continue;
+                }
+                Ok((None, None)) | Err(_) => {
+                    tracing::warn!(target: TARGET, "Failed to retrieve block {}", block_height);
+                    tokio::time::sleep(WAIT_TIMEOUT).await;
+                    continue;
+                }
+            };
+
+            if let Err(e) = self.add_pending_block(block_height, block).await {
+                tracing::error!(target: TARGET, "Failed to save pending block {}: {:?}", block_height, e);
+                tokio::time::sleep(WAIT_TIMEOUT).await;
+                continue;
+            }
+            let save_every_n = self.read_config.save_every_n;
+            if (block_height + 1) % save_every_n == 0 {
+                let starting_block = block_height + 1 - save_every_n;
+                if let Err(e) = self.write_archive(starting_block).await {
+                    tracing::error!(target: TARGET, "Failed to write the archive of block {}: {:?}", starting_block, e);
+                    tokio::time::sleep(WAIT_TIMEOUT).await;
+                    continue;
+                }
+                self.clear_pending().await;
+                // Keep the progress, so the next archive file can be resumed after a restart
+                if let Err(e) = self.add_pending_block(block_height, None).await {
+                    tracing::error!(target: TARGET, "Failed to save the progress at block {}: {:?}", block_height, e);
+                }
+            }
+            block_height += 1;
+        }
+    }
+
+    /// Resumes from the last pending block if the next block is still cached, otherwise starts from
+    /// the next archive file.
+    async fn start_block_height(&self) -> BlockHeight {
+        let last_pending_block_height =
+            tokio::fs::read_to_string(self.pending_path.join(LAST_BLOCK_FILE))
+                .await
+                .ok()
+                .and_then(|s| s.trim().parse::<BlockHeight>().ok());
+        loop {
+            match self
+                .cache
+                .get_last_block_height(self.chain_id, Finality::Final)
+                .await
+            {
+                Some(last_block_height) => {
+                    if let Some(block_height) = last_pending_block_height
+                        .map(|h| h + 1)
+                        .filter(|h| h + MAX_LAG_BLOCKS > last_block_height)
+                    {
+                        return block_height;
+                    }
+                    self.clear_pending().await;
+                    return self.next_archive_start(last_block_height);
+                }
+                None => {
+                    tracing::warn!(target: TARGET, "The last block height is missing from the cache");
+                    tokio::time::sleep(WAIT_TIMEOUT).await;
+                }
+            }
+        }
+    }
+
+    /// Returns the first block height of the first archive file starting at or after the block.
+    fn next_archive_start(&self, block_height: BlockHeight) -> BlockHeight {
+        block_height.div_ceil(self.read_config.save_every_n) * self.read_config.save_every_n
+    }
+
+    fn pending_block_path(&self, block_height: BlockHeight) -> PathBuf {
+        self.pending_path
+            .join(format!("{:0>12}.json", block_height))
+    }
+
+    async fn add_pending_block(
+        &self,
+        block_height: BlockHeight,
+        block: Option<String>,
+    ) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.pending_path).await?;
+        if let Some(block) = block {
+            tokio::fs::write(self.pending_block_path(block_height), block).await?;
+        }
+        tokio::fs::write(
+            self.pending_path.join(LAST_BLOCK_FILE),
+            block_height.to_string(),
+        )
+        .await
+    }
+
+    async fn clear_pending(&self) {
+        if let Err(e) = tokio::fs::remove_dir_all(&self.pending_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::error!(target: TARGET, "Failed to clear the pending blocks: {:?}", e);
+            }
+        }
+    }
+
+    /// Writes the archive file and its seek index from the pending blocks, unless the archive
+    /// file already exists.
+    async fn write_archive(&self, starting_block: BlockHeight) -> std::io::Result<()> {
+        let archive_path = Path::new(&self.path).join(archive_filename(
+            &self.read_config,
+            self.chain_id,
+            starting_block,
+        ));
+        let seek_index_path = Path::new(&self.path).join(seek_index_filename(
+            &self.read_config,
+            self.chain_id,
+            starting_block,
+        ));
+        if tokio::fs::try_exists(&archive_path).await? {
+            tracing::warn!(target: TARGET, "The archive {:?} already exists", archive_path);
+            return Ok(());
+        }
+
+        let mut blocks = Vec::new();
+        for block_height in starting_block..starting_block + self.read_config.save_every_n {
+            let block = match tokio::fs::read_to_string(self.pending_block_path(block_height)).await
+            {
+                Ok(block) => Some(block),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            blocks.push((block_height, block));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let (data, seek_index) = encode_archive(starting_block, &blocks)?;
+            // The archive goes first, so the seek index never points to a missing archive
+            write_file_atomically(&archive_path, &data)?;
+            write_file_atomically(&seek_index_path, &seek_index.encode())?;
+            tracing::info!(target: TARGET, "Wrote the archive {:?}", archive_path);
+            Ok(())
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    #[allow(deprecated)]
+    openssl_probe::init_ssl_cert_env_vars();
+    dotenv().ok();
+
+    tracing_subscriber::fmt::Subscriber::builder()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let chain_id = ChainId::try_from(env::var("CHAIN_ID").expect("Missing CHAIN_ID env var"))
+        .expect("Failed to parse CHAIN_ID");
+
+    let cache = RedisCache::open(&env::var("REDIS_URL").expect("Missing REDIS_URL env var"))
+        .expect("Failed to connect to Redis");
+
+    let path = env::var("WRITE_PATH").expect("Missing WRITE_PATH env var");
+    let read_config = ReadConfig {
+        store: Arc::new(FsArchiveStore::new(path.clone())),
+        save_every_n: env::var("SAVE_EVERY_N")
+            .expect("Missing SAVE_EVERY_N env var")
+            .parse()
+            .expect("Failed to parse SAVE_EVERY_N"),
+    };
+    let pending_path = Path::new(&path)
+        .join(chain_id.to_string())
+        .join(PENDING_DIR);
+
+    Archiver {
+        cache,
+        chain_id,
+        path,
+        read_config,
+        pending_path,
+    }
+    .run()
+    .await;
+}
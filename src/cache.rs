@@ -1,13 +1,40 @@
+use crate::compression::{Compression, EncodedBlock};
 use crate::types::*;
 use crate::with_retries;
+use async_trait::async_trait;
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
+use redis::aio::{ConnectionLike, MultiplexedConnection};
+use redis::cluster::{ClusterClient, ClusterClientBuilder};
+use redis::cluster_async::ClusterConnection;
+use redis::sentinel::{Sentinel, SentinelNodeConnectionInfo};
+use redis::streams::StreamReadReply;
+use redis::{ErrorKind, IntoConnectionInfo, RedisFuture, TlsMode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Notify};
 
-const REDIS_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(5000);
-const CACHE_EXPIRATION: std::time::Duration = std::time::Duration::from_secs(60);
-const ARCHIVE_ATTEMPT_CACHE_EXPIRATION: std::time::Duration =
-    CACHE_EXPIRATION.saturating_sub(std::time::Duration::from_secs(5));
+const REDIS_TIMEOUT: Duration = Duration::from_millis(5000);
+/// How long the `XREAD` following the new blocks waits for them, see `RedisCache::wait_for_block`.
+const STREAM_READ_BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// The response timeout of the connection following the new blocks, longer than its `XREAD`.
+const STREAM_READ_RESPONSE_TIMEOUT: Duration =
+    STREAM_READ_BLOCK_TIMEOUT.saturating_add(REDIS_TIMEOUT);
+const STREAM_READ_RETRY_DELAY: Duration = Duration::from_secs(1);
+const CACHE_EXPIRATION: Duration = Duration::from_secs(60);
+const ARCHIVE_ATTEMPT_CACHE_EXPIRATION: Duration =
+    CACHE_EXPIRATION.saturating_sub(Duration::from_secs(5));
+
+/// The number of the next blocks checked to tell a skipped block from an expired one, see
+/// `is_missing_block_skipped`.
+const MAX_NEXT_BLOCKS: BlockHeight = 100;
 
 const TARGET: &str = "cache";
 
+/// Raw block value as stored in the cache.
+type RawBlock = Vec<u8>;
+
 pub(crate) fn finality_suffix(finality: Finality) -> &'static str {
     match finality {
         Finality::Final => "",
@@ -24,153 +51,999 @@ fn block_key(chain_id: ChainId, block_height: BlockHeight, finality: Finality) -
     )
 }
 
-fn last_block_key(chain_id: ChainId, finality: Finality) -> String {
-    format!("meta:{}{}:last_block", chain_id, finality_suffix(finality))
-}
-
-pub(crate) async fn get_last_block_height(
-    redis_client: redis::Client,
+/// The key of a compressed block, separate from the plain block keys, so the other readers of the
+/// plain block keys only get plain JSON.
+fn compressed_block_key(
     chain_id: ChainId,
+    block_height: BlockHeight,
     finality: Finality,
-) -> Option<BlockHeight> {
-    let res: redis::RedisResult<BlockHeight> = with_retries!(redis_client, |connection| async {
-        let key = last_block_key(chain_id, finality);
-        redis::cmd("GET").arg(&key).query_async(connection).await
-    });
-    res.ok()
+) -> String {
+    format!(
+        "bc:{}{}:{}",
+        chain_id,
+        finality_suffix(finality),
+        block_height
+    )
+}
+
+/// Returns the plain block keys of the blocks followed by their compressed block keys.
+fn block_keys(chain_id: ChainId, block_heights: &[BlockHeight], finality: Finality) -> Vec<String> {
+    let plain_keys = block_heights
+        .iter()
+        .map(|block_height| block_key(chain_id, *block_height, finality));
+    let compressed_keys = block_heights
+        .iter()
+        .map(|block_height| compressed_block_key(chain_id, *block_height, finality));
+    plain_keys.chain(compressed_keys).collect()
 }
 
-pub(crate) async fn get_block_and_last_block_height(
-    redis_client: redis::Client,
+/// Decodes the values of the keys returned by `block_keys`.
+fn decode_block_values(mut values: Vec<Option<RawBlock>>) -> Vec<Option<EncodedBlock>> {
+    let compressed_values = values.split_off(values.len() / 2);
+    values
+        .into_iter()
+        .zip(compressed_values)
+        .map(|(value, compressed_value)| {
+            value
+                .map(EncodedBlock::from_bytes)
+                .or_else(|| compressed_value.and_then(EncodedBlock::from_compressed_value))
+        })
+        .collect()
+}
+
+/// Returns the key and the value to cache the block under: the plain JSON and the missing blocks
+/// under the plain block key, the compressed blocks under the compressed block key.
+fn block_entry(
     chain_id: ChainId,
     block_height: BlockHeight,
     finality: Finality,
-) -> redis::RedisResult<(Option<String>, Option<BlockHeight>)> {
-    let res: redis::RedisResult<(Option<String>, Option<String>)> =
-        with_retries!(redis_client, |connection| async {
-            redis::pipe()
-                .cmd("GET")
-                .arg(block_key(chain_id, block_height, finality))
-                .cmd("GET")
-                .arg(last_block_key(chain_id, finality))
+    block: &EncodedBlock,
+) -> (String, RawBlock) {
+    match block.compression {
+        Compression::None => (
+            block_key(chain_id, block_height, finality),
+            block.data.clone(),
+        ),
+        _ => (
+            compressed_block_key(chain_id, block_height, finality),
+            block.to_compressed_value(),
+        ),
+    }
+}
+
+fn last_block_key(chain_id: ChainId, finality: Finality) -> String {
+    format!("meta:{}{}:last_block", chain_id, finality_suffix(finality))
+}
+
+fn last_blocks_stream_key(chain_id: ChainId, finality: Finality) -> String {
+    format!(
+        "meta:{}{}:last_blocks_queue",
+        chain_id,
+        finality_suffix(finality)
+    )
+}
+
+/// Splits the rest of a multi-host URL after the scheme into the credentials (with `@`, if any),
+/// the hosts and the path.
+fn split_multi_host_url(url: &str) -> redis::RedisResult<(&str, Vec<&str>, &str)> {
+    let (authority, path) = url.split_once('/').unwrap_or((url, ""));
+    let (credentials, hosts) = match authority.rfind('@') {
+        Some(i) => authority.split_at(i + 1),
+        None => ("", authority),
+    };
+    let hosts: Vec<&str> = hosts.split(',').filter(|host| !host.is_empty()).collect();
+    if hosts.is_empty() {
+        return Err(redis::RedisError::from((
+            ErrorKind::InvalidClientConfig,
+            "Missing the Redis hosts",
+        )));
+    }
+    Ok((credentials, hosts, path))
+}
+
+fn archive_read_attempt_key(archive_path: &str) -> String {
+    format!("archive_read_attempt:{}", archive_path)
+}
+
+/// The cache of the recent blocks shared by the server, the indexer and the archiver.
+///
+/// The blocks and the last block heights are written by the NEAR Lake indexer, except for the
+/// blocks read from the archive files.
+#[async_trait]
+pub trait BlockCache: Send + Sync {
+    async fn get_last_block_height(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+    ) -> Option<BlockHeight>;
+
+    async fn get_block_and_last_block_height(
+        &self,
+        chain_id: ChainId,
+        block_height: BlockHeight,
+        finality: Finality,
+    ) -> redis::RedisResult<(Option<EncodedBlock>, Option<BlockHeight>)>;
+
+    async fn get_blocks_and_last_block_height(
+        &self,
+        chain_id: ChainId,
+        block_heights: &[BlockHeight],
+        finality: Finality,
+    ) -> redis::RedisResult<(Vec<Option<EncodedBlock>>, Option<BlockHeight>)>;
+
+    /// Caches the blocks. An empty block means the block is missing.
+    ///
+    /// The compressed blocks are cached under separate keys, see `compressed_block_key`.
+    async fn set_multiple_blocks(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+        blocks: Vec<(BlockHeight, EncodedBlock)>,
+    ) -> redis::RedisResult<()>;
+
+    /// Marks the archive file as being read. Returns `false` if it's already being read.
+    async fn acquire_archive_read_attempt(&self, archive_path: &str) -> redis::RedisResult<bool>;
+
+    async fn release_archive_read_attempt(&self, archive_path: &str) -> redis::RedisResult<()>;
+
+    /// Waits until the block height is reached by the last block height or the timeout expires.
+    /// Returns whether the block height was reached.
+    async fn wait_for_block(
+        &self,
+        chain_id: ChainId,
+        block_height: BlockHeight,
+        finality: Finality,
+        max_timeout: Duration,
+    ) -> redis::RedisResult<bool>;
+
+    /// Returns the last block height seen in the cache by this process, without querying the
+    /// cache, so it's known while the cache is unavailable.
+    fn last_known_block_height(&self, chain_id: ChainId, finality: Finality)
+        -> Option<BlockHeight>;
+}
+
+/// The cache in Redis, shared with the NEAR Lake indexer.
+///
+/// The commands share one long-lived multiplexed connection, which is replaced after a connection
+/// error. The new blocks are followed with a blocking `XREAD` on a separate connection, one per
+/// stream, since it would hold up the other commands on the shared one. All the `wait_for_block`
+/// calls wait on it.
+pub struct RedisCache {
+    target: Arc<RedisTarget>,
+    connection: Mutex<ConnectionState>,
+    /// The last block heights seen in Redis, by the last block key.
+    last_block_heights: Mutex<HashMap<String, BlockHeight>>,
+    /// The heights of the newest blocks in the last blocks streams followed by
+    /// `follow_last_blocks`, by the stream key.
+    new_block_heights: Mutex<HashMap<String, watch::Receiver<Option<BlockHeight>>>>,
+}
+
+/// The Redis deployment the commands are sent to, see `RedisCache::open`.
+enum RedisTarget {
+    Single(redis::Client),
+    Cluster {
+        client: ClusterClient,
+        /// The client for the connections following the new blocks, see `connect`.
+        streams_client: ClusterClient,
+    },
+    /// The primary is looked up from the sentinels on every connection, so a failover is picked
+    /// up by the retries.
+    Sentinel {
+        sentinel: tokio::sync::Mutex<Sentinel>,
+        service_name: String,
+        primary_connection_info: SentinelNodeConnectionInfo,
+    },
+}
+
+impl RedisTarget {
+    /// Connects for the short commands or, with `for_stream_reads`, for the blocking `XREAD`
+    /// following the new blocks, with a response timeout longer than its `XREAD`.
+    async fn connect(&self, for_stream_reads: bool) -> redis::RedisResult<RedisConnection> {
+        let response_timeout = if for_stream_reads {
+            STREAM_READ_RESPONSE_TIMEOUT
+        } else {
+            REDIS_TIMEOUT
+        };
+        match self {
+            RedisTarget::Single(client) => Ok(RedisConnection::Single(
+                client
+                    .get_multiplexed_async_connection_with_timeouts(response_timeout, REDIS_TIMEOUT)
+                    .await?,
+            )),
+            RedisTarget::Cluster {
+                client,
+                streams_client,
+            } => {
+                let client = if for_stream_reads {
+                    streams_client
+                } else {
+                    client
+                };
+                Ok(RedisConnection::Cluster(
+                    client.get_async_connection().await?,
+                ))
+            }
+            RedisTarget::Sentinel {
+                sentinel,
+                service_name,
+                primary_connection_info,
+            } => {
+                let client = sentinel
+                    .lock()
+                    .await
+                    .async_master_for(service_name, Some(primary_connection_info))
+                    .await?;
+                Ok(RedisConnection::Single(
+                    client
+                        .get_multiplexed_async_connection_with_timeouts(
+                            response_timeout,
+                            REDIS_TIMEOUT,
+                        )
+                        .await?,
+                ))
+            }
+        }
+    }
+}
+
+/// A connection attempt shared by all the commands waiting for the connection.
+type SharedConnect = Shared<BoxFuture<'static, Result<RedisConnection, Arc<redis::RedisError>>>>;
+
+enum ConnectionState {
+    Disconnected,
+    /// The commands wait for the same connection attempt, so a slow or failing connection costs
+    /// them one attempt, not one attempt each.
+    Connecting(SharedConnect),
+    Connected(RedisConnection),
+}
+
+/// A connection to a single Redis server (a standalone one or a Sentinel-managed primary) or to a
+/// Redis Cluster.
+#[derive(Clone)]
+enum RedisConnection {
+    Single(MultiplexedConnection),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a redis::Cmd) -> RedisFuture<'a, redis::Value> {
+        match self {
+            RedisConnection::Single(connection) => connection.req_packed_command(cmd),
+            RedisConnection::Cluster(connection) => connection.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<redis::Value>> {
+        match self {
+            RedisConnection::Single(connection) => {
+                connection.req_packed_commands(cmd, offset, count)
+            }
+            RedisConnection::Cluster(connection) => {
+                connection.req_packed_commands(cmd, offset, count)
+            }
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            RedisConnection::Single(connection) => connection.get_db(),
+            RedisConnection::Cluster(connection) => connection.get_db(),
+        }
+    }
+}
+
+impl RedisCache {
+    /// Opens the cache at the Redis URL, which is one of:
+    /// - `redis://host:port` or `rediss://host:port` for a single Redis server.
+    /// - `redis+cluster://[user:password@]host:port[,host:port...]` for a Redis Cluster, with
+    ///   some of its nodes.
+    /// - `redis+sentinel://[user:password@]host:port[,host:port...]/service_name[/db]` for a
+    ///   primary managed by Redis Sentinel, with the sentinels' addresses. The credentials and the
+    ///   database are used for the primary.
+    ///
+    /// The `rediss+` schemes use TLS for all the connections.
+    pub fn open(redis_url: &str) -> redis::RedisResult<Self> {
+        let target = if let Some((scheme, rest)) = redis_url.split_once("+cluster://") {
+            let (credentials, hosts, _) = split_multi_host_url(rest)?;
+            let nodes: Vec<String> = hosts
+                .iter()
+                .map(|host| format!("{}://{}{}", scheme, credentials, host))
+                .collect();
+            RedisTarget::Cluster {
+                client: ClusterClientBuilder::new(nodes.clone())
+                    .connection_timeout(REDIS_TIMEOUT)
+                    .response_timeout(REDIS_TIMEOUT)
+                    .build()?,
+                streams_client: ClusterClientBuilder::new(nodes)
+                    .connection_timeout(REDIS_TIMEOUT)
+                    .response_timeout(STREAM_READ_RESPONSE_TIMEOUT)
+                    .build()?,
+            }
+        } else if let Some((scheme, rest)) = redis_url.split_once("+sentinel://") {
+            let (credentials, hosts, path) = split_multi_host_url(rest)?;
+            let (service_name, db) = path.split_once('/').unwrap_or((path, "0"));
+            if service_name.is_empty() {
+                return Err(redis::RedisError::from((
+                    ErrorKind::InvalidClientConfig,
+                    "Missing the Redis Sentinel service name",
+                )));
+            }
+            let sentinels: Vec<String> = hosts
+                .iter()
+                .map(|host| format!("{}://{}", scheme, host))
+                .collect();
+            // Parsed as a URL, so the credentials are decoded the same way as for a single server
+            let primary_redis_info = format!("{}://{}{}/{}", scheme, credentials, hosts[0], db)
+                .into_connection_info()?
+                .redis;
+            RedisTarget::Sentinel {
+                sentinel: tokio::sync::Mutex::new(Sentinel::build(sentinels)?),
+                service_name: service_name.to_string(),
+                primary_connection_info: SentinelNodeConnectionInfo {
+                    tls_mode: (scheme == "rediss").then_some(TlsMode::Secure),
+                    redis_connection_info: Some(primary_redis_info),
+                },
+            }
+        } else {
+            RedisTarget::Single(redis::Client::open(redis_url)?)
+        };
+        Ok(Self {
+            target: Arc::new(target),
+            connection: Mutex::new(ConnectionState::Disconnected),
+            last_block_heights: Mutex::new(HashMap::new()),
+            new_block_heights: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn is_cluster(&self) -> bool {
+        matches!(*self.target, RedisTarget::Cluster { .. })
+    }
+
+    fn connection_state(&self) -> std::sync::MutexGuard<'_, ConnectionState> {
+        self.connection
+            .lock()
+            .expect("The connection lock is poisoned")
+    }
+
+    /// Returns the shared connection, connecting if there is none.
+    ///
+    /// The lock is never held while connecting. The commands arriving during a connection attempt
+    /// wait for that attempt instead of starting their own.
+    async fn connection(&self) -> redis::RedisResult<RedisConnection> {
+        let connect = {
+            let mut state = self.connection_state();
+            match &*state {
+                ConnectionState::Connected(connection) => return Ok(connection.clone()),
+                ConnectionState::Connecting(connect) => connect.clone(),
+                ConnectionState::Disconnected => {
+                    let target = self.target.clone();
+                    let connect = async move { target.connect(false).await.map_err(Arc::new) }
+                        .boxed()
+                        .shared();
+                    *state = ConnectionState::Connecting(connect.clone());
+                    connect
+                }
+            }
+        };
+        let res = connect.clone().await;
+        let mut state = self.connection_state();
+        if matches!(&*state, ConnectionState::Connecting(current) if current.ptr_eq(&connect)) {
+            *state = match &res {
+                Ok(connection) => ConnectionState::Connected(connection.clone()),
+                Err(_) => ConnectionState::Disconnected,
+            };
+        }
+        res.map_err(|err| {
+            redis::RedisError::from((err.kind(), "Failed to connect to Redis", err.to_string()))
+        })
+    }
+
+    /// Returns the heights of the newest blocks in the last blocks stream, following the stream
+    /// if it's not followed yet.
+    fn new_block_heights(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+    ) -> watch::Receiver<Option<BlockHeight>> {
+        let stream_key = last_blocks_stream_key(chain_id, finality);
+        self.new_block_heights
+            .lock()
+            .expect("The new block heights lock is poisoned")
+            .entry(stream_key.clone())
+            .or_insert_with(|| {
+                let (sender, receiver) = watch::channel(None);
+                tokio::spawn(follow_last_blocks(self.target.clone(), stream_key, sender));
+                receiver
+            })
+            .clone()
+    }
+
+    /// Drops the shared connection if the error means it's broken or, for Sentinel, no longer
+    /// connected to the primary, so the next attempt reconnects.
+    async fn handle_error(&self, err: &redis::RedisError) {
+        if err.is_unrecoverable_error() || err.is_timeout() || err.kind() == ErrorKind::ReadOnly {
+            let mut state = self.connection_state();
+            if matches!(*state, ConnectionState::Connected(_)) {
+                *state = ConnectionState::Disconnected;
+            }
+        }
+    }
+
+    /// Runs the command reading the blocks and reads the last block height.
+    ///
+    /// In a Redis Cluster the block keys and the last block key hash to different slots, so they
+    /// can't share a pipeline. The last block height is read first, so a block missing at or
+    /// below it is never one that was being cached at the same time.
+    async fn query_blocks_and_last_block_height<T: redis::FromRedisValue>(
+        &self,
+        blocks_cmd: redis::Cmd,
+        chain_id: ChainId,
+        finality: Finality,
+    ) -> redis::RedisResult<(T, Option<String>)> {
+        let last_block_key = last_block_key(chain_id, finality);
+        if self.is_cluster() {
+            with_retries!(self, |connection: &mut RedisConnection| {
+                let mut connection = connection.clone();
+                let (blocks_cmd, last_block_key) = (&blocks_cmd, &last_block_key);
+                async move {
+                    let last_block_height: Option<String> = redis::cmd("GET")
+                        .arg(last_block_key)
+                        .query_async(&mut connection)
+                        .await?;
+                    let blocks: T = blocks_cmd.query_async(&mut connection).await?;
+                    Ok((blocks, last_block_height))
+                }
+            })
+        } else {
+            with_retries!(self, |connection| async {
+                redis::pipe()
+                    .add_command(blocks_cmd.clone())
+                    .cmd("GET")
+                    .arg(&last_block_key)
+                    .query_async(connection)
+                    .await
+            })
+        }
+    }
+
+    fn track_last_block_height(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+        last_block_height: Option<BlockHeight>,
+    ) -> Option<BlockHeight> {
+        if let Some(last_block_height) = last_block_height {
+            self.last_block_heights
+                .lock()
+                .expect("The last block heights lock is poisoned")
+                .insert(last_block_key(chain_id, finality), last_block_height);
+        }
+        last_block_height
+    }
+
+    #[allow(dead_code)]
+    pub(crate) async fn set_block(
+        &self,
+        chain_id: ChainId,
+        block_height: BlockHeight,
+        finality: Finality,
+        block: &str,
+    ) -> Result<(), redis::RedisError> {
+        with_retries!(self, |connection| async {
+            let key = block_key(chain_id, block_height, finality);
+            redis::cmd("SET")
+                .arg(&key)
+                .arg(block)
+                .arg("EX")
+                .arg(CACHE_EXPIRATION.as_secs())
                 .query_async(connection)
                 .await
+        })
+    }
+}
+
+#[async_trait]
+impl BlockCache for RedisCache {
+    async fn get_last_block_height(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+    ) -> Option<BlockHeight> {
+        let res: redis::RedisResult<BlockHeight> = with_retries!(self, |connection| async {
+            let key = last_block_key(chain_id, finality);
+            redis::cmd("GET").arg(&key).query_async(connection).await
         });
-    let res = res?;
+        self.track_last_block_height(chain_id, finality, res.ok())
+    }
+
+    async fn get_block_and_last_block_height(
+        &self,
+        chain_id: ChainId,
+        block_height: BlockHeight,
+        finality: Finality,
+    ) -> redis::RedisResult<(Option<EncodedBlock>, Option<BlockHeight>)> {
+        let (blocks, last_block_height) = self
+            .get_blocks_and_last_block_height(chain_id, &[block_height], finality)
+            .await?;
+        Ok((blocks.into_iter().next().flatten(), last_block_height))
+    }
+
+    async fn get_blocks_and_last_block_height(
+        &self,
+        chain_id: ChainId,
+        block_heights: &[BlockHeight],
+        finality: Finality,
+    ) -> redis::RedisResult<(Vec<Option<EncodedBlock>>, Option<BlockHeight>)> {
+        let mut blocks_cmd = redis::cmd("MGET");
+        blocks_cmd.arg(block_keys(chain_id, block_heights, finality));
+        let res: (Vec<Option<RawBlock>>, Option<String>) = self
+            .query_blocks_and_last_block_height(blocks_cmd, chain_id, finality)
+            .await?;
+
+        Ok((
+            decode_block_values(res.0),
+            self.track_last_block_height(chain_id, finality, res.1.map(|s| s.parse().unwrap())),
+        ))
+    }
 
-    Ok((res.0, res.1.map(|s| s.parse().unwrap())))
+    async fn set_multiple_blocks(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+        blocks: Vec<(BlockHeight, EncodedBlock)>,
+    ) -> redis::RedisResult<()> {
+        let blocks: Vec<(String, RawBlock)> = blocks
+            .iter()
+            .map(|(block_height, block)| block_entry(chain_id, *block_height, finality, block))
+            .collect();
+        if self.is_cluster() {
+            // The blocks hash to different slots, so they are set one by one
+            let blocks = &blocks;
+            return with_retries!(self, |connection: &mut RedisConnection| {
+                let connection = connection.clone();
+                async move {
+                    futures::future::try_join_all(blocks.iter().map(|(key, block)| {
+                        let mut connection = connection.clone();
+                        async move {
+                            redis::cmd("SET")
+                                .arg(key)
+                                .arg(block.as_slice())
+                                .arg("EX")
+                                .arg(CACHE_EXPIRATION.as_secs())
+                                .query_async::<_, ()>(&mut connection)
+                                .await
+                        }
+                    }))
+                    .await
+                    .map(|_| ())
+                }
+            });
+        }
+        with_retries!(self, |connection| async {
+            let mut pipe = redis::pipe();
+            for (key, block) in &blocks {
+                pipe.cmd("SET")
+                    .arg(key)
+                    .arg(block.as_slice())
+                    .arg("EX")
+                    .arg(CACHE_EXPIRATION.as_secs());
+            }
+            pipe.query_async(connection).await
+        })
+    }
+
+    async fn acquire_archive_read_attempt(&self, archive_path: &str) -> redis::RedisResult<bool> {
+        with_retries!(self, |connection| async {
+            let key = archive_read_attempt_key(archive_path);
+            redis::cmd("SET")
+                .arg(&key)
+                .arg("1")
+                .arg("NX")
+                .arg("EX")
+                .arg(ARCHIVE_ATTEMPT_CACHE_EXPIRATION.as_secs())
+                .query_async(connection)
+                .await
+        })
+        .map(|res: Option<String>| res.is_some())
+    }
+
+    async fn release_archive_read_attempt(&self, archive_path: &str) -> redis::RedisResult<()> {
+        with_retries!(self, |connection| async {
+            let key = archive_read_attempt_key(archive_path);
+            redis::cmd("DEL").arg(&key).query_async(connection).await
+        })
+    }
+
+    async fn wait_for_block(
+        &self,
+        chain_id: ChainId,
+        block_height: BlockHeight,
+        finality: Finality,
+        max_timeout: Duration,
+    ) -> redis::RedisResult<bool> {
+        let mut new_block_heights = self.new_block_heights(chain_id, finality);
+        let res = tokio::time::timeout(
+            max_timeout,
+            new_block_heights.wait_for(|new_block_height| {
+                new_block_height.is_some_and(|new_block_height| new_block_height >= block_height)
+            }),
+        )
+        .await;
+        match res {
+            Ok(Ok(_)) => Ok(true),
+            Ok(Err(_)) => Err(redis::RedisError::from((
+                ErrorKind::ClientError,
+                "Stopped following the last blocks stream",
+            ))),
+            Err(_) => Ok(false),
+        }
+    }
+
+    fn last_known_block_height(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+    ) -> Option<BlockHeight> {
+        self.last_block_heights
+            .lock()
+            .expect("The last block heights lock is poisoned")
+            .get(&last_block_key(chain_id, finality))
+            .copied()
+    }
 }
 
-#[allow(dead_code)]
-pub(crate) async fn set_block(
-    redis_client: redis::Client,
-    chain_id: ChainId,
-    block_height: BlockHeight,
-    finality: Finality,
-    block: &str,
-) -> Result<(), redis::RedisError> {
-    with_retries!(redis_client, |connection| async {
-        let key = block_key(chain_id, block_height, finality);
-        redis::cmd("SET")
-            .arg(&key)
-            .arg(block)
-            .arg("EX")
-            .arg(CACHE_EXPIRATION.as_secs())
-            .query_async(connection)
-            .await
-    })
+/// Follows the last blocks stream with a blocking `XREAD` on its own connection, publishing the
+/// height of the newest block, until the receivers are dropped.
+///
+/// The stream entry IDs are the block heights.
+async fn follow_last_blocks(
+    target: Arc<RedisTarget>,
+    stream_key: String,
+    sender: watch::Sender<Option<BlockHeight>>,
+) {
+    let mut connection = None;
+    let mut last_id = "$".to_string();
+    while !sender.is_closed() {
+        let res = match connection.as_mut() {
+            Some(connection) => {
+                redis::cmd("XREAD")
+                    .arg("BLOCK")
+                    .arg(STREAM_READ_BLOCK_TIMEOUT.as_millis() as u64)
+                    .arg("STREAMS")
+                    .arg(&stream_key)
+                    .arg(&last_id)
+                    .query_async(connection)
+                    .await
+            }
+            None => match target.connect(true).await {
+                Ok(new_connection) => {
+                    connection = Some(new_connection);
+                    continue;
+                }
+                Err(err) => Err(err),
+            },
+        };
+        let reply: Option<StreamReadReply> = match res {
+            Ok(reply) => reply,
+            Err(err) => {
+                tracing::warn!(target: TARGET, "Error following {}: {}", stream_key, err);
+                connection = None;
+                tokio::time::sleep(STREAM_READ_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let Some(entry) = reply
+            .into_iter()
+            .flat_map(|reply| reply.keys)
+            .flat_map(|stream| stream.ids)
+            .last()
+        else {
+            continue;
+        };
+        if let Some(block_height) = entry
+            .id
+            .split('-')
+            .next()
+            .and_then(|block_height| block_height.parse().ok())
+        {
+            sender.send_replace(Some(block_height));
+        }
+        last_id = entry.id;
+    }
 }
 
-pub(crate) async fn acquire_archive_read_attempt(
-    redis_client: redis::Client,
-    archive_path: &str,
-) -> Result<bool, redis::RedisError> {
-    with_retries!(redis_client, |connection| async {
-        let key = format!("archive_read_attempt:{}", archive_path);
-        redis::cmd("SET")
-            .arg(&key)
-            .arg("1")
-            .arg("NX")
-            .arg("EX")
-            .arg(ARCHIVE_ATTEMPT_CACHE_EXPIRATION.as_secs())
-            .query_async(connection)
-            .await
-    })
-    .map(|res: Option<String>| res.is_some())
+/// A cache in the process memory, to run a single node without Redis, e.g. an archive node.
+///
+/// Nothing writes the new blocks to it, so the last block heights are set with
+/// `set_last_block_height`. The blocks expire the same way as in Redis.
+#[derive(Default)]
+pub struct InMemoryCache {
+    state: Mutex<InMemoryState>,
+    last_block_changed: Notify,
+}
+
+#[derive(Default)]
+struct InMemoryState {
+    /// The blocks and the expiration times by the plain block keys.
+    blocks: HashMap<String, (EncodedBlock, Instant)>,
+    last_block_heights: HashMap<String, BlockHeight>,
+    archive_read_attempts: HashMap<String, Instant>,
+}
+
+impl InMemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_last_block_height(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+        block_height: BlockHeight,
+    ) {
+        self.state()
+            .last_block_heights
+            .insert(last_block_key(chain_id, finality), block_height);
+        self.last_block_changed.notify_waiters();
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, InMemoryState> {
+        self.state.lock().expect("The cache lock is poisoned")
+    }
+}
+
+impl InMemoryState {
+    fn get_block(&self, key: &str) -> Option<EncodedBlock> {
+        self.blocks
+            .get(key)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(block, _)| block.clone())
+    }
+}
+
+#[async_trait]
+impl BlockCache for InMemoryCache {
+    async fn get_last_block_height(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+    ) -> Option<BlockHeight> {
+        self.state()
+            .last_block_heights
+            .get(&last_block_key(chain_id, finality))
+            .copied()
+    }
+
+    async fn get_block_and_last_block_height(
+        &self,
+        chain_id: ChainId,
+        block_height: BlockHeight,
+        finality: Finality,
+    ) -> redis::RedisResult<(Option<EncodedBlock>, Option<BlockHeight>)> {
+        let state = self.state();
+        Ok((
+            state.get_block(&block_key(chain_id, block_height, finality)),
+            state
+                .last_block_heights
+                .get(&last_block_key(chain_id, finality))
+                .copied(),
+        ))
+    }
+
+    async fn get_blocks_and_last_block_height(
+        &self,
+        chain_id: ChainId,
+        block_heights: &[BlockHeight],
+        finality: Finality,
+    ) -> redis::RedisResult<(Vec<Option<EncodedBlock>>, Option<BlockHeight>)> {
+        let state = self.state();
+        Ok((
+            block_heights
+                .iter()
+                .map(|block_height| state.get_block(&block_key(chain_id, *block_height, finality)))
+                .collect(),
+            state
+                .last_block_heights
+                .get(&last_block_key(chain_id, finality))
+                .copied(),
+        ))
+    }
+
+    async fn set_multiple_blocks(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+        blocks: Vec<(BlockHeight, EncodedBlock)>,
+    ) -> redis::RedisResult<()> {
+        let now = Instant::now();
+        let mut state = self.state();
+        state.blocks.retain(|_, (_, expires_at)| *expires_at > now);
+        for (block_height, block) in blocks {
+            state.blocks.insert(
+                block_key(chain_id, block_height, finality),
+                (block, now + CACHE_EXPIRATION),
+            );
+        }
+        Ok(())
+    }
+
+    async fn acquire_archive_read_attempt(&self, archive_path: &str) -> redis::RedisResult<bool> {
+        let now = Instant::now();
+        let mut state = self.state();
+        let key = archive_read_attempt_key(archive_path);
+        if state
+            .archive_read_attempts
+            .get(&key)
+            .is_some_and(|expires_at| *expires_at > now)
+        {
+            return Ok(false);
+        }
+        state
+            .archive_read_attempts
+            .insert(key, now + ARCHIVE_ATTEMPT_CACHE_EXPIRATION);
+        Ok(true)
+    }
+
+    async fn release_archive_read_attempt(&self, archive_path: &str) -> redis::RedisResult<()> {
+        self.state()
+            .archive_read_attempts
+            .remove(&archive_read_attempt_key(archive_path));
+        Ok(())
+    }
+
+    async fn wait_for_block(
+        &self,
+        chain_id: ChainId,
+        block_height: BlockHeight,
+        finality: Finality,
+        max_timeout: Duration,
+    ) -> redis::RedisResult<bool> {
+        let deadline = tokio::time::Instant::now() + max_timeout;
+        loop {
+            // Created before the check, so the change between the check and the wait isn't missed
+            let last_block_changed = self.last_block_changed.notified();
+            if self
+                .get_last_block_height(chain_id, finality)
+                .await
+                .is_some_and(|last_block_height| last_block_height >= block_height)
+            {
+                return Ok(true);
+            }
+            if tokio::time::timeout_at(deadline, last_block_changed)
+                .await
+                .is_err()
+            {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn last_known_block_height(
+        &self,
+        chain_id: ChainId,
+        finality: Finality,
+    ) -> Option<BlockHeight> {
+        self.state()
+            .last_block_heights
+            .get(&last_block_key(chain_id, finality))
+            .copied()
+    }
 }
 
-pub(crate) async fn wait_for_block(
-    redis_client: redis::Client,
+/// Tells whether a block missing from the cache at or below the last block height was skipped or
+/// has expired.
+///
+/// The blocks between a block and its previous block (`prev_height`) were skipped, so it's told by
+/// the next cached block.
+///
+/// # Returns
+///
+/// `None` if none of the next blocks up to the last block height is cached.
+pub async fn is_missing_block_skipped(
+    cache: &dyn BlockCache,
     chain_id: ChainId,
     block_height: BlockHeight,
+    last_block_height: BlockHeight,
     finality: Finality,
-    max_timeout: std::time::Duration,
-) -> redis::RedisResult<()> {
-    let id = format!("{}-0", block_height - 1);
-    let key = format!(
-        "meta:{}{}:last_blocks_queue",
-        chain_id,
-        finality_suffix(finality)
-    );
-    let _res: redis::Value = with_retries!(redis_client, |connection| async {
-        redis::cmd("XREAD")
-            .arg("BLOCK")
-            .arg(max_timeout.as_millis() as u64)
-            .arg("STREAMS")
-            .arg(&key)
-            .arg(&id)
-            .query_async(connection)
-            .await
-    })?;
-    Ok(())
+) -> redis::RedisResult<Option<bool>> {
+    let block_heights: Vec<BlockHeight> = (block_height + 1..=last_block_height)
+        .take(MAX_NEXT_BLOCKS as usize)
+        .collect();
+    if block_heights.is_empty() {
+        return Ok(None);
+    }
+    let (blocks, _) = cache
+        .get_blocks_and_last_block_height(chain_id, &block_heights, finality)
+        .await?;
+    // The empty values are skipped blocks as well
+    let Some(next_block) = blocks.into_iter().flatten().find(|block| !block.is_empty()) else {
+        return Ok(None);
+    };
+    let prev_height = next_block
+        .into_string()
+        .ok()
+        .and_then(|block| serde_json::from_str::<serde_json::Value>(&block).ok())
+        .and_then(|block_json| block_json["block"]["header"]["prev_height"].as_u64());
+    Ok(prev_height.map(|prev_height| prev_height < block_height))
 }
 
 pub(crate) fn set_multiple_blocks_async(
-    redis_client: redis::Client,
+    cache: Arc<dyn BlockCache>,
     chain_id: ChainId,
     finality: Finality,
+    compression: Compression,
     blocks: Vec<(BlockHeight, Option<String>)>,
 ) {
-    tokio::spawn((|| async move {
-        if let Err(e) = set_multiple_blocks(redis_client, chain_id, finality, blocks).await {
+    tokio::spawn(async move {
+        let res = match compress_blocks(compression, blocks).await {
+            Ok(blocks) => cache.set_multiple_blocks(chain_id, finality, blocks).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
             tracing::warn!(target: TARGET, "Error setting multiple blocks: {:?}", e);
         } else {
             tracing::debug!(target: TARGET, "Successfully set multiple blocks");
         }
-    })());
+    });
 }
 
-async fn set_multiple_blocks(
-    redis_client: redis::Client,
-    chain_id: ChainId,
-    finality: Finality,
+async fn compress_blocks(
+    compression: Compression,
     blocks: Vec<(BlockHeight, Option<String>)>,
-) -> Result<(), redis::RedisError> {
-    with_retries!(redis_client, |connection| async {
-        let mut pipe = redis::pipe();
-        for (block_height, block) in &blocks {
-            let key = block_key(chain_id, *block_height, finality);
-            pipe.cmd("SET")
-                .arg(&key)
-                .arg(block.as_ref().map(|s| s.as_str()).unwrap_or_default())
-                .arg("EX")
-                .arg(CACHE_EXPIRATION.as_secs());
-        }
-        pipe.query_async(connection).await
+) -> redis::RedisResult<Vec<(BlockHeight, EncodedBlock)>> {
+    // Missing blocks are stored as empty values, so they are never compressed.
+    tokio::task::spawn_blocking(move || {
+        blocks
+            .into_iter()
+            .map(|(block_height, block)| {
+                let block = match block.filter(|block| !block.is_empty()) {
+                    Some(block) => EncodedBlock {
+                        compression,
+                        data: compression.compress(&block)?,
+                    },
+                    None => EncodedBlock::from_string(String::new()),
+                };
+                Ok((block_height, block))
+            })
+            .collect::<std::io::Result<Vec<_>>>()
     })
+    .await
+    .map_err(|e| redis::RedisError::from(std::io::Error::other(e)))?
+    .map_err(redis::RedisError::from)
+}
+
+tokio::task_local! {
+    static FAIL_FAST: bool;
+}
+
+/// Runs the future with the Redis commands failing on the first error instead of retrying, for
+/// the callers that can do without the cache.
+pub async fn fail_fast<F: std::future::Future>(f: F) -> F::Output {
+    FAIL_FAST.scope(true, f).await
+}
+
+/// Whether the Redis commands of the current task fail on the first error, see `fail_fast`.
+pub fn is_fail_fast() -> bool {
+    FAIL_FAST.try_with(|fail_fast| *fail_fast).unwrap_or(false)
 }
 
 #[macro_export]
 macro_rules! with_retries {
-    ($client: expr, $f_async: expr) => {
+    ($redis: expr, $f_async: expr) => {
+        $crate::with_retries!($redis, $redis.connection(), $f_async)
+    };
+    ($redis: expr, $connection: expr, $f_async: expr) => {
         {
             let mut delay = tokio::time::Duration::from_millis(100);
-            let max_retries = 7;
+            let max_retries = if $crate::cache::is_fail_fast() { 1 } else { 7 };
             let mut i = 0;
             loop {
-                let connection =
-                    $client.get_multiplexed_async_connection_with_timeouts(REDIS_TIMEOUT, REDIS_TIMEOUT)
-                    .await;
+                let connection = $connection.await;
                 let err = match connection {
                     Ok(mut connection) => {
                         match $f_async(&mut connection).await {
@@ -181,13 +1054,170 @@ macro_rules! with_retries {
                     Err(err) => err,
                 };
                 tracing::log::error!(target: "redis", "Attempt #{}: connection error {}", i, err);
-                tokio::time::sleep(delay).await;
-                delay *= 2;
+                $redis.handle_error(&err).await;
                 i += 1;
                 if i >= max_retries {
                     break Err(err);
                 }
+                tokio::time::sleep(delay).await;
+                delay *= 2;
             }
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN_ID: ChainId = ChainId::Mainnet;
+
+    fn block(prev_height: BlockHeight) -> EncodedBlock {
+        EncodedBlock::from_string(format!(
+            r#"{{"block":{{"header":{{"prev_height":{}}}}}}}"#,
+            prev_height
+        ))
+    }
+
+    #[test]
+    fn test_block_values() {
+        let plain_block = EncodedBlock::from_string("{}".to_string());
+        let compressed_block = EncodedBlock {
+            compression: Compression::Brotli,
+            data: Compression::Brotli.compress("{}").unwrap(),
+        };
+        let (plain_key, plain_value) = block_entry(CHAIN_ID, 1, Finality::Final, &plain_block);
+        let (compressed_key, compressed_value) =
+            block_entry(CHAIN_ID, 2, Finality::Final, &compressed_block);
+        assert_eq!(plain_key, "b:mainnet:1");
+        assert_eq!(plain_value, b"{}");
+        assert_eq!(compressed_key, "bc:mainnet:2");
+        assert_eq!(
+            block_keys(CHAIN_ID, &[1, 2, 3], Finality::Optimistic),
+            vec![
+                "b:mainnet_opt:1",
+                "b:mainnet_opt:2",
+                "b:mainnet_opt:3",
+                "bc:mainnet_opt:1",
+                "bc:mainnet_opt:2",
+                "bc:mainnet_opt:3",
+            ]
+        );
+
+        let blocks = decode_block_values(vec![
+            Some(plain_value),
+            None,
+            None,
+            None,
+            Some(compressed_value),
+            None,
+        ]);
+        let blocks: Vec<Option<String>> = blocks
+            .into_iter()
+            .map(|block| block.map(|block| block.into_string().unwrap()))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![Some("{}".to_string()), Some("{}".to_string()), None]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_missing_block_skipped() {
+        let cache = InMemoryCache::new();
+        // Block 101 is expired, 103 and 104 are skipped, 105 is an empty value of a skipped block
+        let blocks = vec![
+            (102, block(101)),
+            (105, EncodedBlock::from_string(String::new())),
+            (106, block(102)),
+        ];
+        cache
+            .set_multiple_blocks(CHAIN_ID, Finality::Final, blocks)
+            .await
+            .unwrap();
+
+        let is_skipped = |block_height, last_block_height| {
+            is_missing_block_skipped(
+                &cache,
+                CHAIN_ID,
+                block_height,
+                last_block_height,
+                Finality::Final,
+            )
+        };
+        assert_eq!(is_skipped(101, 106).await.unwrap(), Some(false));
+        assert_eq!(is_skipped(103, 106).await.unwrap(), Some(true));
+        assert_eq!(is_skipped(104, 106).await.unwrap(), Some(true));
+        // None of the next blocks is cached
+        assert_eq!(is_skipped(100, 100).await.unwrap(), None);
+        assert_eq!(is_skipped(106, 110).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_split_multi_host_url() {
+        assert_eq!(
+            split_multi_host_url("localhost:6379").unwrap(),
+            ("", vec!["localhost:6379"], "")
+        );
+        assert_eq!(
+            split_multi_host_url("node1:6379,node2:6379,node3:6379/").unwrap(),
+            ("", vec!["node1:6379", "node2:6379", "node3:6379"], "")
+        );
+        // The password may contain `@`, so the credentials end at the last one
+        assert_eq!(
+            split_multi_host_url("user:p@ss@node1:6379,node2:6379").unwrap(),
+            ("user:p@ss@", vec!["node1:6379", "node2:6379"], "")
+        );
+        assert_eq!(
+            split_multi_host_url(":secret@sentinel1:26379,sentinel2:26379/mymaster/2").unwrap(),
+            (
+                ":secret@",
+                vec!["sentinel1:26379", "sentinel2:26379"],
+                "mymaster/2"
+            )
+        );
+        // Empty hosts are ignored
+        assert_eq!(
+            split_multi_host_url("node1:6379,,node2:6379,").unwrap(),
+            ("", vec!["node1:6379", "node2:6379"], "")
+        );
+
+        for url in ["", "/mymaster", "user:pass@", "user:pass@/mymaster", ",,"] {
+            let err = split_multi_host_url(url).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidClientConfig, "{:?}", url);
+        }
+    }
+
+    #[test]
+    fn test_open_multi_host_url() {
+        let cache = RedisCache::open("redis+cluster://node1:6379,node2:6379").unwrap();
+        assert!(cache.is_cluster());
+
+        let cache =
+            RedisCache::open("redis+sentinel://:secret@sentinel1:26379,sentinel2:26379/mymaster/2")
+                .unwrap();
+        let RedisTarget::Sentinel {
+            service_name,
+            primary_connection_info,
+            ..
+        } = &*cache.target
+        else {
+            panic!("Expected a Sentinel target");
+        };
+        assert_eq!(service_name, "mymaster");
+        let redis_info = primary_connection_info
+            .redis_connection_info
+            .as_ref()
+            .unwrap();
+        assert_eq!(redis_info.db, 2);
+        assert_eq!(redis_info.password.as_deref(), Some("secret"));
+
+        for url in [
+            "redis+sentinel://sentinel1:26379",
+            "redis+sentinel://sentinel1:26379/",
+            "redis+cluster://",
+        ] {
+            assert!(RedisCache::open(url).is_err(), "{:?}", url);
+        }
+    }
+}
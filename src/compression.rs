@@ -0,0 +1,310 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fmt::Display;
+use std::io::{Read, Write};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const ZSTD_LEVEL: i32 = 3;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_BITS: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+/// The version of the compressed values stored in the cache, see `EncodedBlock::to_compressed_value`.
+const COMPRESSED_VALUE_VERSION: u8 = 1;
+
+/// Compression of the blocks stored in the cache.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Brotli,
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Brotli => write!(f, "brotli"),
+        }
+    }
+}
+
+impl TryFrom<String> for Compression {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            "brotli" => Ok(Compression::Brotli),
+            _ => Err(format!("Invalid compression: {}", value)),
+        }
+    }
+}
+
+impl Compression {
+    /// Detects the compression by the magic bytes of the data. Plain JSON has no magic bytes, and
+    /// neither has Brotli.
+    fn detect(data: &[u8]) -> Self {
+        if data.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// The value of the `Content-Encoding` header for the compressed data.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+            Compression::Brotli => Some("br"),
+        }
+    }
+
+    /// The ID of the compression in the compressed values, see `EncodedBlock::to_compressed_value`.
+    fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => 1,
+            Compression::Zstd => 2,
+            Compression::Brotli => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        [
+            Compression::None,
+            Compression::Gzip,
+            Compression::Zstd,
+            Compression::Brotli,
+        ]
+        .into_iter()
+        .find(|compression| compression.id() == id)
+    }
+
+    pub fn compress(&self, data: &str) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.as_bytes().to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data.as_bytes())?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(data.as_bytes(), ZSTD_LEVEL),
+            Compression::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(
+                    Vec::new(),
+                    BROTLI_BUFFER_SIZE,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW_BITS,
+                );
+                encoder.write_all(data.as_bytes())?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+
+    /// Whether the `Accept-Encoding` header value allows this compression. An encoding with
+    /// `q=0` is refused, and the encoding listed by name takes precedence over `*`.
+    pub fn is_accepted(&self, accept_encoding: &str) -> bool {
+        let Some(content_encoding) = self.content_encoding() else {
+            return true;
+        };
+        let mut wildcard_q = None;
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let q = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if name.eq_ignore_ascii_case(content_encoding) {
+                return q > 0.0;
+            }
+            if name == "*" {
+                wildcard_q = Some(q);
+            }
+        }
+        wildcard_q.is_some_and(|q| q > 0.0)
+    }
+}
+
+/// A block as stored in the cache. The block can be either plain JSON or compressed.
+/// An empty block means the block is missing.
+#[derive(Debug, Clone)]
+pub struct EncodedBlock {
+    pub compression: Compression,
+    pub data: Vec<u8>,
+}
+
+impl EncodedBlock {
+    /// Reads a value of the plain block keys, which is plain JSON. The compression is detected in
+    /// case it was written compressed by an older version.
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self {
+            compression: Compression::detect(&data),
+            data,
+        }
+    }
+
+    pub fn from_string(block: String) -> Self {
+        Self {
+            compression: Compression::None,
+            data: block.into_bytes(),
+        }
+    }
+
+    /// Reads a value written by `to_compressed_value`. Returns `None` for an unknown version or
+    /// compression.
+    pub fn from_compressed_value(mut value: Vec<u8>) -> Option<Self> {
+        let [version, id, ..] = value[..] else {
+            return None;
+        };
+        if version != COMPRESSED_VALUE_VERSION {
+            return None;
+        }
+        let compression = Compression::from_id(id)?;
+        value.drain(..2);
+        Some(Self {
+            compression,
+            data: value,
+        })
+    }
+
+    /// Returns the value to store the block under the compressed block keys: the format version,
+    /// the compression ID and the data. The compression can't always be detected from the data,
+    /// e.g. Brotli has no magic bytes.
+    pub fn to_compressed_value(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(self.data.len() + 2);
+        value.push(COMPRESSED_VALUE_VERSION);
+        value.push(self.compression.id());
+        value.extend_from_slice(&self.data);
+        value
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the block as plain JSON, decompressing it if needed.
+    pub fn into_string(self) -> std::io::Result<String> {
+        let data = match self.compression {
+            Compression::None => self.data,
+            Compression::Gzip => {
+                let mut data = Vec::new();
+                GzDecoder::new(self.data.as_slice()).read_to_end(&mut data)?;
+                data
+            }
+            Compression::Zstd => zstd::decode_all(self.data.as_slice())?,
+            Compression::Brotli => {
+                let mut data = Vec::new();
+                brotli::Decompressor::new(self.data.as_slice(), BROTLI_BUFFER_SIZE)
+                    .read_to_end(&mut data)?;
+                data
+            }
+        };
+        String::from_utf8(data)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: &str = r#"{"block":{"header":{"height":1}},"shards":[]}"#;
+
+    const COMPRESSIONS: [Compression; 4] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Brotli,
+    ];
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Compression::detect(BLOCK.as_bytes()), Compression::None);
+        assert_eq!(Compression::detect(&[]), Compression::None);
+        assert_eq!(
+            Compression::detect(&Compression::Gzip.compress(BLOCK).unwrap()),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect(&Compression::Zstd.compress(BLOCK).unwrap()),
+            Compression::Zstd
+        );
+        // Only the prefix matters
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 0]), Compression::Gzip);
+        assert_eq!(Compression::detect(&[0x1f]), Compression::None);
+    }
+
+    #[test]
+    fn test_is_accepted() {
+        let gzip = Compression::Gzip;
+        assert!(gzip.is_accepted("gzip"));
+        assert!(gzip.is_accepted("deflate, gzip, br"));
+        assert!(gzip.is_accepted("GZIP"));
+        assert!(gzip.is_accepted("gzip;q=0.5"));
+        assert!(gzip.is_accepted("*"));
+        assert!(!gzip.is_accepted("gzip;q=0"));
+        assert!(!gzip.is_accepted("gzip; q=0.0, br"));
+        assert!(!gzip.is_accepted("gzip;q=0, *"));
+        assert!(!gzip.is_accepted("*, gzip;q=0"));
+        assert!(!gzip.is_accepted("br, *;q=0"));
+        assert!(gzip.is_accepted("br, *;q=0.1"));
+        assert!(!gzip.is_accepted("br, zstd"));
+        assert!(!gzip.is_accepted("gzipped"));
+        assert!(!gzip.is_accepted(""));
+
+        assert!(Compression::Brotli.is_accepted("gzip, deflate, br"));
+        assert!(!Compression::Brotli.is_accepted("brotli"));
+        assert!(Compression::Zstd.is_accepted("zstd"));
+        // Plain JSON is always accepted
+        assert!(Compression::None.is_accepted(""));
+        assert!(Compression::None.is_accepted("gzip"));
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        for compression in COMPRESSIONS {
+            let block = EncodedBlock {
+                compression,
+                data: compression.compress(BLOCK).unwrap(),
+            };
+            assert_eq!(block.into_string().unwrap(), BLOCK, "{}", compression);
+        }
+    }
+
+    #[test]
+    fn test_compressed_value_round_trip() {
+        for compression in COMPRESSIONS {
+            let data = compression.compress(BLOCK).unwrap();
+            let value = EncodedBlock {
+                compression,
+                data: data.clone(),
+            }
+            .to_compressed_value();
+            let block = EncodedBlock::from_compressed_value(value).unwrap();
+            assert_eq!(block.compression, compression);
+            assert_eq!(block.data, data);
+        }
+    }
+
+    #[test]
+    fn test_compressed_value_unknown_format() {
+        assert!(EncodedBlock::from_compressed_value(vec![]).is_none());
+        assert!(EncodedBlock::from_compressed_value(vec![COMPRESSED_VALUE_VERSION]).is_none());
+        assert!(
+            EncodedBlock::from_compressed_value(vec![COMPRESSED_VALUE_VERSION, 9, 0]).is_none()
+        );
+        assert!(EncodedBlock::from_compressed_value(vec![2, 1, 0]).is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// A set of field paths to keep in a JSON document, e.g. `block.header.height,shards.shard_id`.
+///
+/// Paths are dot-separated object keys. When a path reaches an array, the rest of the path is
+/// applied to every element of the array.
+#[derive(Debug, Default)]
+pub struct FieldSelection {
+    /// Whether the whole value is selected.
+    all: bool,
+    children: BTreeMap<String, FieldSelection>,
+}
+
+impl FieldSelection {
+    /// Parses a comma-separated list of dot-separated paths. Returns `None` for an empty or
+    /// malformed list.
+    pub fn parse(fields: &str) -> Option<Self> {
+        let mut selection = FieldSelection::default();
+        for path in fields.split(',').map(str::trim) {
+            if path.is_empty() || path.split('.').any(str::is_empty) {
+                return None;
+            }
+            let mut node = &mut selection;
+            for key in path.split('.') {
+                node = node.children.entry(key.to_string()).or_default();
+            }
+            node.all = true;
+        }
+        Some(selection)
+    }
+
+    /// Returns a copy of the value with only the selected fields.
+    pub fn project(&self, value: &Value) -> Value {
+        if self.all {
+            return value.clone();
+        }
+        match value {
+            Value::Object(object) => Value::Object(
+                self.children
+                    .iter()
+                    .filter_map(|(key, selection)| {
+                        object
+                            .get(key)
+                            .map(|value| (key.clone(), selection.project(value)))
+                    })
+                    .collect::<Map<String, Value>>(),
+            ),
+            Value::Array(values) => {
+                Value::Array(values.iter().map(|value| self.project(value)).collect())
+            }
+            _ => Value::Null,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn project(fields: &str, value: &Value) -> Value {
+        FieldSelection::parse(fields).unwrap().project(value)
+    }
+
+    fn block() -> Value {
+        json!({
+            "block": {
+                "author": "node.near",
+                "header": {"height": 100, "hash": "abc", "prev_height": 99},
+            },
+            "shards": [
+                {"shard_id": 0, "chunk": {"transactions": [{"hash": "t1", "nonce": 1}]}},
+                {"shard_id": 1, "chunk": null},
+            ],
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(FieldSelection::parse("block.header.height").is_some());
+        assert!(FieldSelection::parse(" block.header , shards ").is_some());
+        for fields in ["", ",", "block,", "block..header", ".block", "block."] {
+            assert!(FieldSelection::parse(fields).is_none(), "{:?}", fields);
+        }
+    }
+
+    #[test]
+    fn test_project_nested_objects() {
+        assert_eq!(
+            project("block.header.height,block.author", &block()),
+            json!({"block": {"author": "node.near", "header": {"height": 100}}})
+        );
+        // A selected field keeps its whole value, even if its children are also selected
+        assert_eq!(
+            project("block.header,block.header.height", &block()),
+            json!({"block": {"header": {"height": 100, "hash": "abc", "prev_height": 99}}})
+        );
+    }
+
+    #[test]
+    fn test_project_arrays() {
+        assert_eq!(
+            project("shards.shard_id", &block()),
+            json!({"shards": [{"shard_id": 0}, {"shard_id": 1}]})
+        );
+        assert_eq!(
+            project("shards.chunk.transactions.hash", &block()),
+            json!({"shards": [{"chunk": {"transactions": [{"hash": "t1"}]}}, {"chunk": null}]})
+        );
+        assert_eq!(project("shards.shard_id", &json!([])), json!([]));
+    }
+
+    #[test]
+    fn test_project_missing_paths() {
+        assert_eq!(project("block.missing", &block()), json!({"block": {}}));
+        assert_eq!(project("missing.height", &block()), json!({}));
+        assert_eq!(
+            project("shards.missing", &block()),
+            json!({"shards": [{}, {}]})
+        );
+    }
+
+    #[test]
+    fn test_project_paths_through_scalars() {
+        assert_eq!(
+            project("block.author.name", &block()),
+            json!({"block": {"author": null}})
+        );
+        assert_eq!(
+            project("block.header.height.value", &block()),
+            json!({"block": {"header": {"height": null}}})
+        );
+        assert_eq!(project("block", &json!(null)), json!(null));
+    }
+}
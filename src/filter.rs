@@ -0,0 +1,398 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Filter for the receipt execution outcomes of a block.
+/// An empty list means the corresponding field is not filtered.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ReceiptFilter {
+    #[serde(default)]
+    pub receiver_ids: Vec<String>,
+    #[serde(default)]
+    pub method_names: Vec<String>,
+    #[serde(default)]
+    pub shard_ids: Vec<u64>,
+}
+
+impl ReceiptFilter {
+    /// Returns the shards of the block with only the matching receipt execution outcomes.
+    /// Shards without matching receipts are omitted.
+    pub fn filter_shards(&self, block_json: &Value) -> Vec<Value> {
+        block_json
+            .get("shards")
+            .and_then(|shards| shards.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|shard| {
+                let shard_id = shard["shard_id"].as_u64()?;
+                if !self.shard_ids.is_empty() && !self.shard_ids.contains(&shard_id) {
+                    return None;
+                }
+                let outcomes: Vec<Value> = shard["receipt_execution_outcomes"]
+                    .as_array()?
+                    .iter()
+                    .filter(|outcome| self.matches_receipt(&outcome["receipt"]))
+                    .cloned()
+                    .collect();
+                if outcomes.is_empty() {
+                    None
+                } else {
+                    Some(json!({
+                        "shard_id": shard_id,
+                        "receipt_execution_outcomes": outcomes,
+                    }))
+                }
+            })
+            .collect()
+    }
+
+    fn matches_receipt(&self, receipt: &Value) -> bool {
+        if !self.receiver_ids.is_empty() {
+            let receiver_id = receipt["receiver_id"].as_str().unwrap_or_default();
+            if !self.receiver_ids.iter().any(|id| id == receiver_id) {
+                return false;
+            }
+        }
+        if !self.method_names.is_empty() {
+            return receipt["receipt"]["Action"]["actions"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|action| action["FunctionCall"]["method_name"].as_str())
+                .any(|method_name| self.method_names.iter().any(|m| m == method_name));
+        }
+        true
+    }
+}
+
+/// Filter for the parts of a block touching an account as a signer, a predecessor, a receiver
+/// or an executor.
+#[derive(Debug, Clone)]
+pub struct AccountFilter {
+    pub account_id: String,
+}
+
+impl AccountFilter {
+    /// Returns the shards of the block with only the transactions, receipts, receipt execution
+    /// outcomes and state changes touching the account. Shards without matches are omitted.
+    pub fn filter_shards(&self, block_json: &Value) -> Vec<Value> {
+        block_json
+            .get("shards")
+            .and_then(|shards| shards.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|shard| {
+                let transactions = filter_array(&shard["chunk"]["transactions"], |tx| {
+                    self.is_account(&tx["transaction"]["signer_id"])
+                        || self.is_account(&tx["transaction"]["receiver_id"])
+                });
+                let receipts = filter_array(&shard["chunk"]["receipts"], |receipt| {
+                    self.matches_receipt(receipt)
+                });
+                let receipt_execution_outcomes =
+                    filter_array(&shard["receipt_execution_outcomes"], |outcome| {
+                        self.matches_receipt(&outcome["receipt"])
+                            || self
+                                .is_account(&outcome["execution_outcome"]["outcome"]["executor_id"])
+                    });
+                let state_changes = filter_array(&shard["state_changes"], |state_change| {
+                    self.is_account(&state_change["change"]["account_id"])
+                });
+                if transactions.is_empty()
+                    && receipts.is_empty()
+                    && receipt_execution_outcomes.is_empty()
+                    && state_changes.is_empty()
+                {
+                    return None;
+                }
+                Some(json!({
+                    "shard_id": shard["shard_id"],
+                    "transactions": transactions,
+                    "receipts": receipts,
+                    "receipt_execution_outcomes": receipt_execution_outcomes,
+                    "state_changes": state_changes,
+                }))
+            })
+            .collect()
+    }
+
+    fn matches_receipt(&self, receipt: &Value) -> bool {
+        self.is_account(&receipt["predecessor_id"])
+            || self.is_account(&receipt["receiver_id"])
+            || self.is_account(&receipt["receipt"]["Action"]["signer_id"])
+    }
+
+    fn is_account(&self, account_id: &Value) -> bool {
+        account_id.as_str() == Some(self.account_id.as_str())
+    }
+}
+
+const EVENT_LOG_PREFIX: &str = "EVENT_JSON:";
+
+/// Filter for the NEP-297 events logged by the receipt execution outcomes of a block.
+/// A missing field is not filtered.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct EventFilter {
+    pub standard: Option<String>,
+    pub event: Option<String>,
+}
+
+impl EventFilter {
+    /// Parses the `EVENT_JSON:` logs of all receipt execution outcomes of the block and returns
+    /// the matching events together with their receipt, transaction, contract and shard.
+    pub fn extract_events(&self, block_json: &Value) -> Vec<Value> {
+        let mut events = vec![];
+        for shard in block_json["shards"].as_array().into_iter().flatten() {
+            for outcome in shard["receipt_execution_outcomes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                let execution_outcome = &outcome["execution_outcome"];
+                let logs = execution_outcome["outcome"]["logs"].as_array();
+                for (log_index, log) in logs.into_iter().flatten().enumerate() {
+                    let Some(event) = log
+                        .as_str()
+                        .and_then(|log| log.strip_prefix(EVENT_LOG_PREFIX))
+                        .and_then(|event| serde_json::from_str::<Value>(event.trim()).ok())
+                    else {
+                        continue;
+                    };
+                    if !self.matches_event(&event) {
+                        continue;
+                    }
+                    events.push(json!({
+                        "standard": event["standard"],
+                        "version": event["version"],
+                        "event": event["event"],
+                        "data": event["data"],
+                        "account_id": execution_outcome["outcome"]["executor_id"],
+                        "receipt_id": execution_outcome["id"],
+                        "tx_hash": outcome["tx_hash"],
+                        "shard_id": shard["shard_id"],
+                        "log_index": log_index,
+                    }));
+                }
+            }
+        }
+        events
+    }
+
+    fn matches_event(&self, event: &Value) -> bool {
+        let matches = |expected: &Option<String>, value: &Value| {
+            expected
+                .as_ref()
+                .is_none_or(|expected| value.as_str() == Some(expected.as_str()))
+        };
+        matches(&self.standard, &event["standard"]) && matches(&self.event, &event["event"])
+    }
+}
+
+/// Filter for the state changes of a block. A missing field is not filtered.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct StateChangeFilter {
+    pub account_id: Option<String>,
+    #[serde(rename = "type")]
+    pub change_type: Option<String>,
+}
+
+impl StateChangeFilter {
+    /// Returns the matching state changes from all shards of the block, each annotated with its
+    /// `shard_id`.
+    pub fn extract_state_changes(&self, block_json: &Value) -> Vec<Value> {
+        let mut state_changes = vec![];
+        for shard in block_json["shards"].as_array().into_iter().flatten() {
+            for mut state_change in filter_array(&shard["state_changes"], |state_change| {
+                self.matches_state_change(state_change)
+            }) {
+                if let Some(state_change) = state_change.as_object_mut() {
+                    state_change.insert("shard_id".to_string(), shard["shard_id"].clone());
+                }
+                state_changes.push(state_change);
+            }
+        }
+        state_changes
+    }
+
+    fn matches_state_change(&self, state_change: &Value) -> bool {
+        if let Some(account_id) = &self.account_id {
+            if state_change["change"]["account_id"].as_str() != Some(account_id.as_str()) {
+                return false;
+            }
+        }
+        if let Some(change_type) = &self.change_type {
+            if state_change["type"].as_str() != Some(change_type.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Returns the cloned elements of a JSON array matching the predicate.
+fn filter_array<F>(values: &Value, f: F) -> Vec<Value>
+where
+    F: Fn(&Value) -> bool,
+{
+    values
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|value| f(value))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_filter_matches_receipt() {
+        let receipt = json!({
+            "predecessor_id": "relayer.near",
+            "receiver_id": "contract.near",
+            "receipt": {"Action": {"signer_id": "alice.near", "actions": []}},
+        });
+        let matches = |account_id: &str| {
+            AccountFilter {
+                account_id: account_id.to_string(),
+            }
+            .matches_receipt(&receipt)
+        };
+        assert!(matches("relayer.near"));
+        assert!(matches("contract.near"));
+        assert!(matches("alice.near"));
+        assert!(!matches("bob.near"));
+    }
+
+    fn block_with_logs() -> Value {
+        json!({
+            "shards": [
+                {"shard_id": 0, "receipt_execution_outcomes": []},
+                {"shard_id": 3, "receipt_execution_outcomes": [{
+                    "tx_hash": "tx1",
+                    "execution_outcome": {
+                        "id": "receipt1",
+                        "outcome": {
+                            "executor_id": "token.near",
+                            "logs": [
+                                "Transfer 10 from alice.near to bob.near",
+                                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[{"amount":"10"}]}"#,
+                                "EVENT_JSON:{\"standard\":",
+                                r#"EVENT_JSON: {"standard":"nep171","version":"1.0.0","event":"nft_mint","data":[]}"#,
+                            ],
+                        },
+                    },
+                }]},
+            ],
+        })
+    }
+
+    #[test]
+    fn test_event_filter_extract_events() {
+        let events = EventFilter::default().extract_events(&block_with_logs());
+        // The non-event log and the malformed event are skipped
+        assert_eq!(
+            events,
+            vec![
+                json!({
+                    "standard": "nep141",
+                    "version": "1.0.0",
+                    "event": "ft_transfer",
+                    "data": [{"amount": "10"}],
+                    "account_id": "token.near",
+                    "receipt_id": "receipt1",
+                    "tx_hash": "tx1",
+                    "shard_id": 3,
+                    "log_index": 1,
+                }),
+                json!({
+                    "standard": "nep171",
+                    "version": "1.0.0",
+                    "event": "nft_mint",
+                    "data": [],
+                    "account_id": "token.near",
+                    "receipt_id": "receipt1",
+                    "tx_hash": "tx1",
+                    "shard_id": 3,
+                    "log_index": 3,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_filter_matches_event() {
+        let events = |standard: Option<&str>, event: Option<&str>| -> Vec<Value> {
+            EventFilter {
+                standard: standard.map(str::to_string),
+                event: event.map(str::to_string),
+            }
+            .extract_events(&block_with_logs())
+            .into_iter()
+            .map(|event| event["event"].clone())
+            .collect()
+        };
+        assert_eq!(events(Some("nep141"), None), vec![json!("ft_transfer")]);
+        assert_eq!(events(None, Some("nft_mint")), vec![json!("nft_mint")]);
+        assert_eq!(
+            events(Some("nep141"), Some("ft_transfer")),
+            vec![json!("ft_transfer")]
+        );
+        assert_eq!(
+            events(Some("nep141"), Some("nft_mint")),
+            Vec::<Value>::new()
+        );
+        assert_eq!(events(Some("nep999"), None), Vec::<Value>::new());
+    }
+
+    #[test]
+    fn test_state_change_filter() {
+        let block_json = json!({
+            "shards": [
+                {"shard_id": 0, "state_changes": [
+                    {"type": "account_update", "change": {"account_id": "alice.near"}},
+                    {"type": "data_update", "change": {"account_id": "token.near"}},
+                ]},
+                {"shard_id": 1, "state_changes": []},
+                {"shard_id": 2, "state_changes": [
+                    {"type": "account_update", "change": {"account_id": "token.near"}},
+                ]},
+            ],
+        });
+        let state_changes = |account_id: Option<&str>, change_type: Option<&str>| {
+            StateChangeFilter {
+                account_id: account_id.map(str::to_string),
+                change_type: change_type.map(str::to_string),
+            }
+            .extract_state_changes(&block_json)
+        };
+        // The state changes of all shards, each with its shard ID
+        assert_eq!(
+            state_changes(None, None),
+            vec![
+                json!({"type": "account_update", "change": {"account_id": "alice.near"}, "shard_id": 0}),
+                json!({"type": "data_update", "change": {"account_id": "token.near"}, "shard_id": 0}),
+                json!({"type": "account_update", "change": {"account_id": "token.near"}, "shard_id": 2}),
+            ]
+        );
+        assert_eq!(
+            state_changes(Some("token.near"), None),
+            vec![
+                json!({"type": "data_update", "change": {"account_id": "token.near"}, "shard_id": 0}),
+                json!({"type": "account_update", "change": {"account_id": "token.near"}, "shard_id": 2}),
+            ]
+        );
+        assert_eq!(
+            state_changes(None, Some("account_update")),
+            vec![
+                json!({"type": "account_update", "change": {"account_id": "alice.near"}, "shard_id": 0}),
+                json!({"type": "account_update", "change": {"account_id": "token.near"}, "shard_id": 2}),
+            ]
+        );
+        assert_eq!(
+            state_changes(Some("alice.near"), Some("data_update")),
+            Vec::<Value>::new()
+        );
+    }
+}
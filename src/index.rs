@@ -0,0 +1,511 @@
+use crate::cache::{is_missing_block_skipped, BlockCache};
+use crate::pool::BlockingPool;
+use crate::reader::{read_blocks, ArchiveBlock};
+use crate::types::*;
+use crate::ReadConfig;
+use redb::{Database, ReadableTable, TableDefinition, WriteTransaction};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+
+const TARGET: &str = "index";
+
+/// Maps a transaction hash to the block height and the shard ID where it was included.
+const TX_TABLE: TableDefinition<&str, (BlockHeight, u64)> = TableDefinition::new("tx");
+/// Maps a receipt ID to the block height and the shard ID where it was executed.
+const RECEIPT_TABLE: TableDefinition<&str, (BlockHeight, u64)> = TableDefinition::new("receipt");
+/// Maps a chunk hash to the block height and the shard ID where it was included.
+const CHUNK_TABLE: TableDefinition<&str, (BlockHeight, u64)> = TableDefinition::new("chunk");
+/// Maps a block hash to the block height.
+const BLOCK_HASH_TABLE: TableDefinition<&str, BlockHeight> = TableDefinition::new("block_hash");
+const META_TABLE: TableDefinition<&str, BlockHeight> = TableDefinition::new("meta");
+/// Maps the first block height of a range of blocks that expired from the cache before they were
+/// indexed to the block height after the range. They are indexed from the archive files.
+const GAP_TABLE: TableDefinition<BlockHeight, BlockHeight> = TableDefinition::new("gap");
+
+const LAST_INDEXED_BLOCK_KEY: &str = "last_indexed_block";
+/// The first block height of the historical blocks already recorded to be indexed, see
+/// `add_backfill`.
+const BACKFILL_FROM_KEY: &str = "backfill_from";
+const INDEXER_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// The indexer only resumes from the last indexed block if it's still expected to be cached.
+const MAX_RESUME_BLOCKS: BlockHeight = 10;
+/// How often the gaps are retried, since their archive files are written later.
+const BACKFILL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Boxed `redb::Error`, since the original error is too large to be returned by value.
+#[derive(Debug)]
+pub struct IndexError(Box<redb::Error>);
+
+impl<E: Into<redb::Error>> From<E> for IndexError {
+    fn from(err: E) -> Self {
+        IndexError(Box::new(err.into()))
+    }
+}
+
+impl std::fmt::Display for IndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Block location of a chunk, a transaction or a receipt.
+#[derive(Debug, Copy, Clone)]
+pub struct Location {
+    pub block_height: BlockHeight,
+    pub shard_id: u64,
+}
+
+pub struct BlockIndex {
+    db: Database,
+}
+
+impl BlockIndex {
+    pub fn open(path: &str) -> Result<Self, IndexError> {
+        let db = Database::create(path)?;
+        // Create the tables, so the read transactions don't fail on an empty database.
+        let txn = db.begin_write()?;
+        txn.open_table(TX_TABLE)?;
+        txn.open_table(RECEIPT_TABLE)?;
+        txn.open_table(BLOCK_HASH_TABLE)?;
+        txn.open_table(CHUNK_TABLE)?;
+        txn.open_table(META_TABLE)?;
+        txn.open_table(GAP_TABLE)?;
+        txn.commit()?;
+        Ok(Self { db })
+    }
+
+    pub fn get_tx(&self, tx_hash: &str) -> Result<Option<Location>, IndexError> {
+        self.get(TX_TABLE, tx_hash)
+    }
+
+    pub fn get_receipt(&self, receipt_id: &str) -> Result<Option<Location>, IndexError> {
+        self.get(RECEIPT_TABLE, receipt_id)
+    }
+
+    pub fn get_chunk(&self, chunk_hash: &str) -> Result<Option<Location>, IndexError> {
+        self.get(CHUNK_TABLE, chunk_hash)
+    }
+
+    pub fn get_block_hash(&self, block_hash: &str) -> Result<Option<BlockHeight>, IndexError> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(BLOCK_HASH_TABLE)?;
+        Ok(table.get(block_hash)?.map(|value| value.value()))
+    }
+
+    fn get(
+        &self,
+        table: TableDefinition<&str, (BlockHeight, u64)>,
+        key: &str,
+    ) -> Result<Option<Location>, IndexError> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(table)?;
+        Ok(table.get(key)?.map(|value| {
+            let (block_height, shard_id) = value.value();
+            Location {
+                block_height,
+                shard_id,
+            }
+        }))
+    }
+
+    fn last_indexed_block_height(&self) -> Result<Option<BlockHeight>, IndexError> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(META_TABLE)?;
+        Ok(table
+            .get(LAST_INDEXED_BLOCK_KEY)?
+            .map(|value| value.value()))
+    }
+
+    /// Returns the ranges of blocks that expired from the cache before they were indexed, as the
+    /// first block height and the block height after the range.
+    fn gaps(&self) -> Result<Vec<(BlockHeight, BlockHeight)>, IndexError> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(GAP_TABLE)?;
+        let gaps = table
+            .iter()?
+            .map(|entry| entry.map(|(start, end)| (start.value(), end.value())))
+            .collect::<Result<_, _>>()?;
+        Ok(gaps)
+    }
+
+    /// Records the range of blocks that expired from the cache before they were indexed, merging
+    /// it with the gap right before it.
+    fn add_gap(&self, start: BlockHeight, end: BlockHeight) -> Result<(), IndexError> {
+        let txn = self.db.begin_write()?;
+        insert_gap(&txn, start, end)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Records the historical blocks from `start` to `end` (exclusive) to be indexed from the
+    /// archive files by `run_backfill`.
+    ///
+    /// The range is recorded once, so it's not indexed again after a restart. If a range was
+    /// recorded before, only the blocks before it are added.
+    pub fn add_backfill(&self, start: BlockHeight, end: BlockHeight) -> Result<(), IndexError> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(META_TABLE)?;
+            let end = match table.get(BACKFILL_FROM_KEY)? {
+                Some(backfill_from) => end.min(backfill_from.value()),
+                None => end,
+            };
+            if start >= end {
+                return Ok(());
+            }
+            table.insert(BACKFILL_FROM_KEY, start)?;
+            insert_gap(&txn, start, end)?;
+        }
+        txn.commit()?;
+        tracing::info!(target: TARGET, "Indexing blocks {}..{} from the archive", start, end);
+        Ok(())
+    }
+
+    /// Indexes the blocks at the start of the gap, replacing the gap with the rest of it.
+    fn fill_gap(
+        &self,
+        start: BlockHeight,
+        blocks: &[(BlockHeight, Option<String>)],
+        rest: Option<(BlockHeight, BlockHeight)>,
+    ) -> Result<(), IndexError> {
+        let txn = self.db.begin_write()?;
+        insert_blocks(&txn, blocks)?;
+        {
+            let mut table = txn.open_table(GAP_TABLE)?;
+            table.remove(start)?;
+            if let Some((start, end)) = rest {
+                table.insert(start, end)?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Indexes the block hashes, the chunk hashes, the transactions and the receipts of the given
+    /// blocks in one write transaction.
+    /// Missing blocks are skipped.
+    pub fn index_blocks(
+        &self,
+        blocks: &[(BlockHeight, Option<String>)],
+        last_indexed_block_height: Option<BlockHeight>,
+    ) -> Result<(), IndexError> {
+        let txn = self.db.begin_write()?;
+        insert_blocks(&txn, blocks)?;
+        if let Some(block_height) = last_indexed_block_height {
+            txn.open_table(META_TABLE)?
+                .insert(LAST_INDEXED_BLOCK_KEY, block_height)?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+/// Inserts the gap, merging it with the gap right before it.
+fn insert_gap(
+    txn: &WriteTransaction,
+    start: BlockHeight,
+    end: BlockHeight,
+) -> Result<(), IndexError> {
+    let mut table = txn.open_table(GAP_TABLE)?;
+    let previous_gap = table
+        .range(..=start)?
+        .next_back()
+        .transpose()?
+        .map(|(start, end)| (start.value(), end.value()));
+    match previous_gap {
+        Some((previous_start, previous_end)) if previous_end >= start => {
+            table.insert(previous_start, previous_end.max(end))?;
+        }
+        _ => {
+            table.insert(start, end)?;
+        }
+    }
+    Ok(())
+}
+
+/// Inserts the blocks into the index tables. Missing blocks are skipped.
+fn insert_blocks(
+    txn: &WriteTransaction,
+    blocks: &[(BlockHeight, Option<String>)],
+) -> Result<(), IndexError> {
+    let mut tx_table = txn.open_table(TX_TABLE)?;
+    let mut receipt_table = txn.open_table(RECEIPT_TABLE)?;
+    let mut block_hash_table = txn.open_table(BLOCK_HASH_TABLE)?;
+    let mut chunk_table = txn.open_table(CHUNK_TABLE)?;
+    for (block_height, block) in blocks {
+        let Some(block) = block.as_ref().filter(|block| !block.is_empty()) else {
+            continue;
+        };
+        let Ok(block_json) = serde_json::from_str::<Value>(block) else {
+            tracing::warn!(target: TARGET, "Failed to parse block {}", block_height);
+            continue;
+        };
+        if let Some(block_hash) = block_json["block"]["header"]["hash"].as_str() {
+            block_hash_table.insert(block_hash, *block_height)?;
+        }
+        for shard in block_json["shards"].as_array().into_iter().flatten() {
+            let shard_id = shard["shard_id"].as_u64().unwrap_or_default();
+            if let Some(chunk_hash) = shard["chunk"]["header"]["chunk_hash"].as_str() {
+                chunk_table.insert(chunk_hash, (*block_height, shard_id))?;
+            }
+            for tx in shard["chunk"]["transactions"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                if let Some(tx_hash) = tx["transaction"]["hash"].as_str() {
+                    tx_table.insert(tx_hash, (*block_height, shard_id))?;
+                }
+            }
+            for outcome in shard["receipt_execution_outcomes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                if let Some(receipt_id) = outcome["receipt"]["receipt_id"].as_str() {
+                    receipt_table.insert(receipt_id, (*block_height, shard_id))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Indexes the blocks read from an archive file in the background.
+pub(crate) fn index_blocks_async(
+    index: Arc<BlockIndex>,
+    blocks: Vec<(BlockHeight, Option<String>)>,
+) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = index.index_blocks(&blocks, None) {
+            tracing::warn!(target: TARGET, "Error indexing blocks: {:?}", e);
+        } else {
+            tracing::debug!(target: TARGET, "Successfully indexed {} blocks", blocks.len());
+        }
+    });
+}
+
+/// Follows the newly cached finalized blocks and indexes them.
+///
+/// Resumes from the last indexed block if it's still cached, otherwise starts from the last block.
+/// The blocks that expired from the cache before they were indexed are recorded as gaps, indexed
+/// from the archive files by `run_backfill`.
+pub async fn run_indexer(index: Arc<BlockIndex>, cache: Arc<dyn BlockCache>, chain_id: ChainId) {
+    let finality = Finality::Final;
+    let mut block_height = loop {
+        let last_indexed_block_height = index.last_indexed_block_height().unwrap_or_else(|e| {
+            tracing::warn!(target: TARGET, "Error reading the last indexed block: {:?}", e);
+            None
+        });
+        match cache.get_last_block_height(chain_id, finality).await {
+            Some(last_block_height) => {
+                let resume_block_height = last_indexed_block_height.map(|h| h + 1);
+                if let Some(block_height) = resume_block_height
+                    .filter(|h| *h > last_block_height.saturating_sub(MAX_RESUME_BLOCKS))
+                {
+                    break block_height;
+                }
+                if let Some(block_height) = resume_block_height.filter(|h| *h < last_block_height) {
+                    tracing::warn!(target: TARGET, "Blocks {}..{} have expired from the cache, indexing them from the archive", block_height, last_block_height);
+                    if let Err(e) = index.add_gap(block_height, last_block_height) {
+                        tracing::error!(target: TARGET, "Error recording the gap at block {}: {:?}", block_height, e);
+                    }
+                }
+                break last_block_height;
+            }
+            None => {
+                tracing::warn!(target: TARGET, "The last block height is missing from the cache");
+                tokio::time::sleep(INDEXER_WAIT_TIMEOUT).await;
+            }
+        }
+    };
+    tracing::info!(target: TARGET, "Indexing new blocks from {}", block_height);
+
+    loop {
+        let res = cache
+            .get_block_and_last_block_height(chain_id, block_height, finality)
+            .await;
+        // `None` if the block has to be indexed from the archive file
+        let block = match res {
+            Ok((Some(block), _)) => match block.into_string() {
+                Ok(block) => Some(Some(block)),
+                Err(e) => {
+                    tracing::warn!(target: TARGET, "Failed to decode block {}, indexing it from the archive: {:?}", block_height, e);
+                    None
+                }
+            },
+            Ok((None, Some(last_block_height))) if block_height <= last_block_height => {
+                let res = is_missing_block_skipped(
+                    cache.as_ref(),
+                    chain_id,
+                    block_height,
+                    last_block_height,
+                    finality,
+                )
+                .await;
+                match res {
+                    Ok(Some(true)) => Some(None),
+                    Ok(_) => {
+                        tracing::warn!(target: TARGET, "Block {} has expired from the cache, indexing it from the archive", block_height);
+                        None
+                    }
+                    Err(e) => {
+                        tracing::warn!(target: TARGET, "Failed to check whether block {} was skipped: {:?}", block_height, e);
+                        tokio::time::sleep(INDEXER_WAIT_TIMEOUT).await;
+                        continue;
+                    }
+                }
+            }
+            Ok((None, Some(_))) => {
+                if let Err(e) = cache
+                    .wait_for_block(chain_id, block_height, finality, INDEXER_WAIT_TIMEOUT)
+                    .await
+                {
+                    tracing::warn!(target: TARGET, "Error waiting for block {}: {:?}", block_height, e);
+                    tokio::time::sleep(INDEXER_WAIT_TIMEOUT).await;
+                }
+                continue;
+            }
+            Ok((None, None)) | Err(_) => {
+                tracing::warn!(target: TARGET, "Failed to retrieve block {}", block_height);
+                tokio::time::sleep(INDEXER_WAIT_TIMEOUT).await;
+                continue;
+            }
+        };
+        let index = index.clone();
+        let res = tokio::task::spawn_blocking(move || match block {
+            Some(block) => index.index_blocks(&[(block_height, block)], Some(block_height)),
+            None => index
+                .add_gap(block_height, block_height + 1)
+                .and_then(|()| index.index_blocks(&[], Some(block_height))),
+        })
+        .await;
+        match res {
+            Ok(Ok(())) => block_height += 1,
+            Ok(Err(e)) => {
+                tracing::error!(target: TARGET, "Error indexing block {}: {:?}", block_height, e);
+                tokio::time::sleep(INDEXER_WAIT_TIMEOUT).await;
+            }
+            Err(e) => {
+                tracing::error!(target: TARGET, "Indexing task failed for block {}: {:?}", block_height, e);
+                tokio::time::sleep(INDEXER_WAIT_TIMEOUT).await;
+            }
+        }
+    }
+}
+
+/// Indexes the gaps left by `run_indexer` and the historical blocks recorded by `add_backfill`
+/// from the archive files. A gap is retried until its archive files are written and readable.
+pub async fn run_backfill(
+    index: Arc<BlockIndex>,
+    read_config: ReadConfig,
+    pool: Arc<BlockingPool>,
+    chain_id: ChainId,
+) {
+    loop {
+        match index.gaps() {
+            Ok(gaps) => {
+                for (start, end) in gaps {
+                    backfill_gap(&index, &read_config, &pool, chain_id, start, end).await;
+                }
+            }
+            Err(e) => {
+                tracing::error!(target: TARGET, "Error reading the gaps: {:?}", e);
+            }
+        }
+        tokio::time::sleep(BACKFILL_INTERVAL).await;
+    }
+}
+
+/// Indexes the gap one archive file at a time, until an archive file can't be read.
+async fn backfill_gap(
+    index: &Arc<BlockIndex>,
+    read_config: &ReadConfig,
+    pool: &BlockingPool,
+    chain_id: ChainId,
+    mut start: BlockHeight,
+    end: BlockHeight,
+) {
+    while start < end {
+        let archive_end = (start / read_config.save_every_n + 1) * read_config.save_every_n;
+        let gap_end = end.min(archive_end);
+        let blocks: Option<Vec<(BlockHeight, Option<String>)>> =
+            read_blocks(read_config, pool, chain_id, start)
+                .await
+                .into_iter()
+                .filter(|(block_height, _)| (start..gap_end).contains(block_height))
+                .map(|(block_height, block)| match block {
+                    ArchiveBlock::Block(block) => Some((block_height, Some(block))),
+                    ArchiveBlock::Skipped => Some((block_height, None)),
+                    ArchiveBlock::Unavailable(_) => None,
+                })
+                .collect();
+        let Some(blocks) = blocks else {
+            tracing::debug!(target: TARGET, "The archive of block {} is unavailable, the gap is retried later", start);
+            return;
+        };
+        let rest = (gap_end < end).then_some((gap_end, end));
+        let index = index.clone();
+        let res = tokio::task::spawn_blocking(move || index.fill_gap(start, &blocks, rest)).await;
+        match res {
+            Ok(Ok(())) => {
+                tracing::info!(target: TARGET, "Indexed blocks {}..{} from the archive", start, gap_end);
+                start = gap_end;
+            }
+            Ok(Err(e)) => {
+                tracing::error!(target: TARGET, "Error indexing the gap at block {}: {:?}", start, e);
+                return;
+            }
+            Err(e) => {
+                tracing::error!(target: TARGET, "Indexing task failed for the gap at block {}: {:?}", start, e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gaps() {
+        let path = std::env::temp_dir().join(format!("neardata-index-test-{}", std::process::id()));
+        let index = BlockIndex::open(&path.to_string_lossy()).unwrap();
+
+        index.add_gap(10, 11).unwrap();
+        index.add_gap(11, 12).unwrap();
+        index.add_gap(20, 25).unwrap();
+        index.add_gap(21, 23).unwrap();
+        assert_eq!(index.gaps().unwrap(), vec![(10, 12), (20, 25)]);
+
+        let block = r#"{"block":{"header":{"hash":"hash20"}},"shards":[]}"#;
+        index
+            .fill_gap(
+                20,
+                &[(20, Some(block.to_string())), (21, None)],
+                Some((22, 25)),
+            )
+            .unwrap();
+        assert_eq!(index.gaps().unwrap(), vec![(10, 12), (22, 25)]);
+        assert_eq!(index.get_block_hash("hash20").unwrap(), Some(20));
+
+        index.fill_gap(10, &[], None).unwrap();
+        assert_eq!(index.gaps().unwrap(), vec![(22, 25)]);
+
+        // The backfill is recorded once, and only extended to the earlier blocks
+        index.add_backfill(30, 40).unwrap();
+        index.add_backfill(30, 40).unwrap();
+        assert_eq!(index.gaps().unwrap(), vec![(22, 25), (30, 40)]);
+        index.fill_gap(30, &[], None).unwrap();
+        index.add_backfill(35, 50).unwrap();
+        assert_eq!(index.gaps().unwrap(), vec![(22, 25)]);
+        index.fill_gap(22, &[], None).unwrap();
+        index.add_backfill(20, 40).unwrap();
+        assert_eq!(index.gaps().unwrap(), vec![(20, 30)]);
+
+        drop(index);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
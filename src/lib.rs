@@ -0,0 +1,65 @@
+pub mod api;
+pub mod cache;
+pub mod compression;
+pub mod fields;
+pub mod filter;
+pub mod index;
+pub mod memory_cache;
+pub mod pool;
+pub mod reader;
+pub mod seek_index;
+pub mod types;
+pub mod writer;
+
+use std::sync::Arc;
+
+use crate::cache::BlockCache;
+use crate::compression::Compression;
+use crate::index::BlockIndex;
+use crate::memory_cache::MemoryBlockCache;
+use crate::pool::BlockingPool;
+use crate::reader::BlockArchiveStore;
+use crate::types::{BlockHeight, ChainId};
+use actix_web::http::header;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+
+#[derive(Clone)]
+pub struct ReadConfig {
+    pub store: Arc<dyn BlockArchiveStore>,
+    pub save_every_n: u64,
+}
+
+#[derive(Clone)]
+pub struct ArchiveConfig {
+    pub archive_boundaries: Vec<BlockHeight>,
+    pub domain_name: String,
+    /// The index of the archive boundary that this node is responsible for.
+    /// E.g. If there are 2 boundaries:
+    /// - `0` -> means from genesis to the first archive boundary (exclusive).
+    /// - `1` -> means from the first archive boundary to the second archive boundary (exclusive).
+    /// - `2` -> means from the second archive boundary to the blockchain head.
+    pub archive_index: usize,
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pub cache: Arc<dyn BlockCache>,
+    pub read_config: Option<ReadConfig>,
+    pub chain_id: ChainId,
+    pub genesis_block_height: BlockHeight,
+    /// Whether this node has the latest blocks and uses archive files.
+    /// If not, it means this is an archive node.
+    pub is_latest: bool,
+    /// Whether this node has the freshest blocks, but doesn't use archive files
+    pub is_fresh: bool,
+    pub archive_config: Option<ArchiveConfig>,
+    pub max_healthy_latency_ms: u128,
+    /// The index of block hashes, chunk hashes, transaction hashes and receipt IDs, if enabled.
+    pub block_index: Option<Arc<BlockIndex>>,
+    /// The compression of the blocks written to the cache from the archive files.
+    pub cache_compression: Compression,
+    /// The pool decoding the archive files, shared by all workers.
+    pub archive_read_pool: Arc<BlockingPool>,
+    /// The in-process cache of the finalized blocks, shared by all workers.
+    pub memory_cache: Arc<MemoryBlockCache>,
+}
@@ -1,52 +1,30 @@
-mod api;
-mod cache;
-mod reader;
-mod types;
-
 use dotenv::dotenv;
 use std::env;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-use crate::types::{BlockHeight, ChainId};
 use actix_cors::Cors;
 use actix_web::http::header;
-use actix_web::{get, middleware, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::{middleware, web, App, HttpResponse, HttpServer, Responder};
+use neardata_server::cache::{BlockCache, InMemoryCache, RedisCache};
+use neardata_server::compression::Compression;
+use neardata_server::index::{self, BlockIndex};
+use neardata_server::memory_cache::MemoryBlockCache;
+use neardata_server::pool::BlockingPool;
+use neardata_server::reader::{
+    BlockArchiveStore, FsArchiveStore, HttpArchiveStore, S3ArchiveStore,
+};
+use neardata_server::types::{BlockHeight, ChainId, Finality};
+use neardata_server::{api, AppState, ArchiveConfig, ReadConfig};
 use tracing_subscriber::EnvFilter;
 
 pub static INDEX_HTML: &str = include_str!("../static/index.html");
 pub static SKILL_MD: &str = include_str!("../static/skill.md");
 
-#[derive(Clone)]
-pub struct ReadConfig {
-    pub path: String,
-    pub save_every_n: u64,
-}
-
-#[derive(Clone)]
-pub struct ArchiveConfig {
-    pub archive_boundaries: Vec<BlockHeight>,
-    pub domain_name: String,
-    /// The index of the archive boundary that this node is responsible for.
-    /// E.g. If there are 2 boundaries:
-    /// - `0` -> means from genesis to the first archive boundary (exclusive).
-    /// - `1` -> means from the first archive boundary to the second archive boundary (exclusive).
-    /// - `2` -> means from the second archive boundary to the blockchain head.
-    pub archive_index: usize,
-}
-
-#[derive(Clone)]
-pub struct AppState {
-    pub redis_client: redis::Client,
-    pub read_config: Option<ReadConfig>,
-    pub chain_id: ChainId,
-    pub genesis_block_height: BlockHeight,
-    /// Whether this node has the latest blocks and uses archive files.
-    /// If not, it means this is an archive node.
-    pub is_latest: bool,
-    /// Whether this node has the freshest blocks, but doesn't use archive files
-    pub is_fresh: bool,
-    pub archive_config: Option<ArchiveConfig>,
-    pub max_healthy_latency_ms: u128,
-}
+const DEFAULT_ARCHIVE_READ_QUEUE: usize = 64;
+const DEFAULT_ARCHIVE_READ_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_MEMORY_CACHE_SIZE_MB: usize = 256;
 
 async fn greet() -> impl Responder {
     HttpResponse::Ok()
@@ -60,6 +38,53 @@ async fn skill() -> impl Responder {
         .body(SKILL_MD)
 }
 
+/// Creates the archive store for the `READ_PATH`, which is either a local path, an `s3://bucket/prefix`
+/// location or an `http(s)://` base URL.
+fn archive_store(path: String) -> Arc<dyn BlockArchiveStore> {
+    if let Some(location) = path.strip_prefix("s3://") {
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        let region = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let endpoint = env::var("S3_ENDPOINT").ok();
+        Arc::new(
+            S3ArchiveStore::new(bucket, prefix, &region, endpoint)
+                .expect("Failed to create the S3 archive store"),
+        )
+    } else if path.starts_with("http://") || path.starts_with("https://") {
+        Arc::new(HttpArchiveStore::new(path))
+    } else {
+        Arc::new(FsArchiveStore::new(path))
+    }
+}
+
+/// Creates the cache for a node without Redis. Nothing writes the new blocks to it, so only an
+/// archive node can run without Redis: its last block is the one before its archive boundary, if
+/// any. The archive node reads its archive files without a last block height.
+fn in_memory_cache(
+    chain_id: ChainId,
+    is_latest: bool,
+    archive_config: Option<&ArchiveConfig>,
+) -> InMemoryCache {
+    let cache = InMemoryCache::new();
+    let last_block_height = archive_config
+        .filter(|_| !is_latest)
+        .and_then(|config| config.archive_boundaries.get(config.archive_index))
+        .map(|archive_boundary| archive_boundary - 1);
+    match last_block_height {
+        Some(last_block_height) => {
+            for finality in [Finality::Final, Finality::Optimistic] {
+                cache.set_last_block_height(chain_id, finality, last_block_height);
+            }
+        }
+        None if is_latest => {
+            tracing::warn!(
+                "REDIS_URL is not set, the latest node can't serve new blocks without Redis"
+            )
+        }
+        None => {}
+    }
+    cache
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     #[allow(deprecated)]
@@ -75,12 +100,8 @@ async fn main() -> std::io::Result<()> {
     let chain_id = ChainId::try_from(env::var("CHAIN_ID").expect("Missing CHAIN_ID env var"))
         .expect("Failed to parse CHAIN_ID");
 
-    let redis_client =
-        redis::Client::open(env::var("REDIS_URL").expect("Missing REDIS_URL env var"))
-            .expect("Failed to connect to Redis");
-
     let read_config = env::var("READ_PATH").ok().map(|path| ReadConfig {
-        path,
+        store: archive_store(path),
         save_every_n: env::var("SAVE_EVERY_N")
             .expect("Missing SAVE_EVERY_N env var")
             .parse()
@@ -109,6 +130,17 @@ async fn main() -> std::io::Result<()> {
         None
     };
 
+    let cache: Arc<dyn BlockCache> = match env::var("REDIS_URL") {
+        Ok(redis_url) => {
+            Arc::new(RedisCache::open(&redis_url).expect("Failed to connect to Redis"))
+        }
+        Err(_) => Arc::new(in_memory_cache(
+            chain_id,
+            is_latest,
+            archive_config.as_ref(),
+        )),
+    };
+
     let genesis_block_height = env::var("GENESIS_BLOCK_HEIGHT")
         .expect("Missing GENESIS_BLOCK_HEIGHT env var")
         .parse()
@@ -119,6 +151,84 @@ async fn main() -> std::io::Result<()> {
         .parse()
         .expect("Failed to parse MAX_HEALTHY_LATENCY_MS");
 
+    let cache_compression = env::var("CACHE_COMPRESSION").map_or(Compression::None, |v| {
+        Compression::try_from(v).expect("Failed to parse CACHE_COMPRESSION")
+    });
+
+    let archive_read_pool = Arc::new(BlockingPool::new(
+        env::var("ARCHIVE_READ_CONCURRENCY").map_or_else(
+            |_| thread::available_parallelism().map_or(1, |n| n.get()),
+            |v| v.parse().expect("Failed to parse ARCHIVE_READ_CONCURRENCY"),
+        ),
+        env::var("ARCHIVE_READ_QUEUE").map_or(DEFAULT_ARCHIVE_READ_QUEUE, |v| {
+            v.parse().expect("Failed to parse ARCHIVE_READ_QUEUE")
+        }),
+        Duration::from_millis(
+            env::var("ARCHIVE_READ_TIMEOUT_MS").map_or(DEFAULT_ARCHIVE_READ_TIMEOUT_MS, |v| {
+                v.parse().expect("Failed to parse ARCHIVE_READ_TIMEOUT_MS")
+            }),
+        ),
+    ));
+
+    let memory_cache = Arc::new(MemoryBlockCache::new(
+        env::var("MEMORY_CACHE_SIZE_MB").map_or(DEFAULT_MEMORY_CACHE_SIZE_MB, |v| {
+            v.parse().expect("Failed to parse MEMORY_CACHE_SIZE_MB")
+        }) * 1024
+            * 1024,
+    ));
+
+    let block_index = env::var("INDEX_PATH")
+        .ok()
+        .map(|path| Arc::new(BlockIndex::open(&path).expect("Failed to open the block index")));
+    if let Some(block_index) = &block_index {
+        if let Ok(backfill_from) = env::var("INDEX_BACKFILL_FROM") {
+            let backfill_from: BlockHeight = backfill_from
+                .parse()
+                .expect("Failed to parse INDEX_BACKFILL_FROM");
+            assert!(
+                read_config.is_some(),
+                "INDEX_BACKFILL_FROM requires READ_PATH"
+            );
+            // By default, up to the blocks indexed by this node
+            let backfill_to: BlockHeight = match env::var("INDEX_BACKFILL_TO") {
+                Ok(v) => v.parse().expect("Failed to parse INDEX_BACKFILL_TO"),
+                Err(_) if is_latest => cache
+                    .get_last_block_height(chain_id, Finality::Final)
+                    .await
+                    .expect("The last block height is missing from the cache"),
+                Err(_) => archive_config
+                    .as_ref()
+                    .and_then(|config| config.archive_boundaries.get(config.archive_index))
+                    .map(|archive_boundary| archive_boundary - 1)
+                    .expect("Missing INDEX_BACKFILL_TO env var"),
+            };
+            block_index
+                .add_backfill(backfill_from, backfill_to + 1)
+                .expect("Failed to record the index backfill");
+        }
+        if is_latest {
+            tokio::spawn(index::run_indexer(
+                block_index.clone(),
+                cache.clone(),
+                chain_id,
+            ));
+        }
+        match &read_config {
+            Some(read_config) => {
+                tokio::spawn(index::run_backfill(
+                    block_index.clone(),
+                    read_config.clone(),
+                    archive_read_pool.clone(),
+                    chain_id,
+                ));
+            }
+            None if is_latest => tracing::warn!(
+                "Without READ_PATH, the blocks that expire from the cache before they are indexed are not indexed"
+            ),
+            None => {}
+        }
+    }
+
     HttpServer::new(move || {
         // Configure CORS middleware
         let cors = Cors::default()
@@ -128,6 +238,7 @@ async fn main() -> std::io::Result<()> {
                 header::CONTENT_TYPE,
                 header::AUTHORIZATION,
                 header::ACCEPT,
+                header::HeaderName::from_static("last-event-id"),
             ])
             .max_age(3600)
             .supports_credentials();
@@ -138,10 +249,22 @@ async fn main() -> std::io::Result<()> {
             .service(api::v0::get_last_block)
             .service(api::v0::get_block_headers)
             .service(api::v0::get_shard)
-            .service(api::v0::get_chunk);
+            .service(api::v0::get_chunk)
+            .service(api::v0::get_transaction)
+            .service(api::v0::get_account_activity)
+            .service(api::v0::get_events)
+            .service(api::v0::get_state_changes)
+            .service(api::v0::get_blocks)
+            .service(api::v0::get_archive)
+            .service(api::v0::get_stream)
+            .service(api::v0::get_ws)
+            .service(api::v0::get_tx)
+            .service(api::v0::get_receipt)
+            .service(api::v0::get_block_by_hash)
+            .service(api::v0::get_chunk_by_hash);
         App::new()
             .app_data(web::Data::new(AppState {
-                redis_client: redis_client.clone(),
+                cache: cache.clone(),
                 read_config: read_config.clone(),
                 chain_id,
                 genesis_block_height,
@@ -149,6 +272,10 @@ async fn main() -> std::io::Result<()> {
                 is_fresh,
                 archive_config: archive_config.clone(),
                 max_healthy_latency_ms,
+                block_index: block_index.clone(),
+                cache_compression,
+                archive_read_pool: archive_read_pool.clone(),
+                memory_cache: memory_cache.clone(),
             }))
             .wrap(cors)
             .wrap(middleware::Logger::new(
@@ -0,0 +1,166 @@
+use crate::compression::EncodedBlock;
+use crate::types::*;
+use lru::LruCache;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The usage of a `MemoryBlockCache`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryCacheStats {
+    pub blocks: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// In-process cache of the finalized blocks in front of Redis, bounded by the total size of the
+/// blocks. The least recently used blocks are evicted first.
+///
+/// Only the finalized non-empty blocks are cached, since they never change. The skipped blocks
+/// are left to Redis, since they are not served as immutable either.
+pub struct MemoryBlockCache {
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Inner {
+    blocks: LruCache<BlockHeight, EncodedBlock>,
+    bytes: usize,
+}
+
+impl MemoryBlockCache {
+    /// Creates the cache holding up to `max_bytes` of blocks. A zero size disables the cache.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            inner: Mutex::new(Inner {
+                blocks: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, block_height: BlockHeight) -> Option<EncodedBlock> {
+        if self.max_bytes == 0 {
+            return None;
+        }
+        let block = self
+            .inner
+            .lock()
+            .expect("The memory cache lock is poisoned")
+            .blocks
+            .get(&block_height)
+            .cloned();
+        let counter = if block.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        block
+    }
+
+    /// Caches the finalized block. Empty blocks and blocks larger than the cache are ignored.
+    pub fn insert(&self, block_height: BlockHeight, block: EncodedBlock) {
+        let size = block.data.len();
+        if block.is_empty() || size > self.max_bytes {
+            return;
+        }
+        let mut inner = self
+            .inner
+            .lock()
+            .expect("The memory cache lock is poisoned");
+        if let Some(old_block) = inner.blocks.put(block_height, block) {
+            inner.bytes -= old_block.data.len();
+        }
+        inner.bytes += size;
+        while inner.bytes > self.max_bytes {
+            let Some((_, evicted_block)) = inner.blocks.pop_lru() else {
+                break;
+            };
+            inner.bytes -= evicted_block.data.len();
+        }
+    }
+
+    pub fn stats(&self) -> MemoryCacheStats {
+        let inner = self
+            .inner
+            .lock()
+            .expect("The memory cache lock is poisoned");
+        MemoryCacheStats {
+            blocks: inner.blocks.len(),
+            bytes: inner.bytes,
+            max_bytes: self.max_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: usize) -> EncodedBlock {
+        EncodedBlock::from_string("x".repeat(size))
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = MemoryBlockCache::new(30);
+        cache.insert(1, block(10));
+        cache.insert(2, block(10));
+        cache.insert(3, block(10));
+        // Block 1 becomes the most recently used
+        assert!(cache.get(1).is_some());
+        cache.insert(4, block(15));
+
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(3).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(4).is_some());
+        let stats = cache.stats();
+        assert_eq!((stats.blocks, stats.bytes), (2, 25));
+        assert_eq!((stats.hits, stats.misses), (3, 2));
+    }
+
+    #[test]
+    fn test_overwrite_accounting() {
+        let cache = MemoryBlockCache::new(30);
+        cache.insert(1, block(10));
+        cache.insert(2, block(10));
+        cache.insert(1, block(5));
+        let stats = cache.stats();
+        assert_eq!((stats.blocks, stats.bytes), (2, 15));
+
+        // The larger block replaces the old one without evicting the other block
+        cache.insert(1, block(20));
+        let stats = cache.stats();
+        assert_eq!((stats.blocks, stats.bytes), (2, 30));
+        assert_eq!(cache.get(1).unwrap().data.len(), 20);
+    }
+
+    #[test]
+    fn test_rejects_oversized_and_empty_blocks() {
+        let cache = MemoryBlockCache::new(30);
+        cache.insert(1, block(10));
+        cache.insert(2, block(31));
+        cache.insert(3, block(0));
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(3).is_none());
+        let stats = cache.stats();
+        assert_eq!((stats.blocks, stats.bytes), (1, 10));
+
+        // A disabled cache doesn't keep anything
+        let cache = MemoryBlockCache::new(0);
+        cache.insert(1, block(10));
+        assert!(cache.get(1).is_none());
+        assert_eq!(cache.stats().bytes, 0);
+    }
+}
@@ -0,0 +1,210 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PoolError {
+    /// The queue of tasks waiting for a thread is full.
+    QueueFull,
+    /// The task didn't finish within the timeout, including the time spent in the queue.
+    Timeout,
+    /// The task panicked.
+    Panicked,
+}
+
+/// The saturation of a `BlockingPool`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub running: usize,
+    pub queued: usize,
+    pub max_concurrency: usize,
+    pub max_queue: usize,
+    /// The number of tasks rejected because the queue was full.
+    pub rejected: u64,
+    /// The number of tasks that timed out.
+    pub timed_out: u64,
+}
+
+/// Runs blocking tasks on the tokio blocking threads with bounded concurrency, so a burst of heavy
+/// tasks (e.g. decoding archive files) can't take over the blocking threads.
+///
+/// The tasks beyond `max_concurrency` wait in a queue of up to `max_queue` tasks. The tasks that
+/// don't fit in the queue are rejected right away.
+pub struct BlockingPool {
+    semaphore: Arc<Semaphore>,
+    max_concurrency: usize,
+    max_queue: usize,
+    timeout: Duration,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+/// A place in the queue, released when the task leaves the queue or is cancelled.
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl BlockingPool {
+    pub fn new(max_concurrency: usize, max_queue: usize, timeout: Duration) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrency)),
+            max_concurrency,
+            max_queue,
+            timeout,
+            queued: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs the task on a blocking thread once the pool has capacity.
+    ///
+    /// On timeout the task is not cancelled, it keeps its thread until it finishes, so the pool
+    /// stays bounded.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let deadline = Instant::now() + self.timeout;
+        let permit = if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            permit
+        } else {
+            if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queue {
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(PoolError::QueueFull);
+            }
+            let _slot = QueueSlot(&self.queued);
+            match tokio::time::timeout_at(deadline, self.semaphore.clone().acquire_owned()).await {
+                Ok(permit) => permit.expect("The semaphore is never closed"),
+                Err(_) => {
+                    self.timed_out.fetch_add(1, Ordering::Relaxed);
+                    return Err(PoolError::Timeout);
+                }
+            }
+        };
+        let task = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            f()
+        });
+        match tokio::time::timeout_at(deadline, task).await {
+            Ok(Ok(res)) => Ok(res),
+            Ok(Err(_)) => Err(PoolError::Panicked),
+            Err(_) => {
+                self.timed_out.fetch_add(1, Ordering::Relaxed);
+                Err(PoolError::Timeout)
+            }
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            running: self.max_concurrency - self.semaphore.available_permits(),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_concurrency: self.max_concurrency,
+            max_queue: self.max_queue,
+            rejected: self.rejected.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Starts a task that holds a thread of the pool until the returned sender is dropped.
+    async fn hold(
+        pool: &Arc<BlockingPool>,
+    ) -> (
+        mpsc::Sender<()>,
+        tokio::task::JoinHandle<Result<(), PoolError>>,
+    ) {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let task = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                pool.run(move || {
+                    let _ = receiver.recv();
+                })
+                .await
+            }
+        });
+        (sender, task)
+    }
+
+    async fn wait_until(pool: &BlockingPool, f: impl Fn(&PoolStats) -> bool) {
+        while !f(&pool.stats()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_full() {
+        let pool = Arc::new(BlockingPool::new(1, 1, Duration::from_secs(10)));
+        let (running, running_task) = hold(&pool).await;
+        wait_until(&pool, |stats| stats.running == 1).await;
+        let (queued, queued_task) = hold(&pool).await;
+        wait_until(&pool, |stats| stats.queued == 1).await;
+
+        assert_eq!(pool.run(|| ()).await, Err(PoolError::QueueFull));
+        let stats = pool.stats();
+        assert_eq!(
+            (stats.running, stats.queued, stats.rejected, stats.timed_out),
+            (1, 1, 1, 0)
+        );
+
+        drop(running);
+        drop(queued);
+        assert_eq!(running_task.await.unwrap(), Ok(()));
+        assert_eq!(queued_task.await.unwrap(), Ok(()));
+        assert_eq!(pool.run(|| 1).await, Ok(1));
+        let stats = pool.stats();
+        assert_eq!(
+            (stats.running, stats.queued, stats.rejected, stats.timed_out),
+            (0, 0, 1, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let pool = Arc::new(BlockingPool::new(1, 1, Duration::from_millis(200)));
+        let (running, running_task) = hold(&pool).await;
+        wait_until(&pool, |stats| stats.running == 1).await;
+
+        // The queued task times out waiting for a thread
+        assert_eq!(pool.run(|| ()).await, Err(PoolError::Timeout));
+        assert_eq!(pool.stats().queued, 0);
+        // The running task times out, but keeps its thread until it finishes
+        assert_eq!(running_task.await.unwrap(), Err(PoolError::Timeout));
+        let stats = pool.stats();
+        assert_eq!(
+            (stats.running, stats.queued, stats.rejected, stats.timed_out),
+            (1, 0, 0, 2)
+        );
+
+        drop(running);
+        wait_until(&pool, |stats| stats.running == 0).await;
+        assert_eq!(pool.run(|| 1).await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_panicked() {
+        let pool = BlockingPool::new(1, 1, Duration::from_secs(10));
+        assert_eq!(
+            pool.run(|| panic!("test")).await,
+            Err::<(), _>(PoolError::Panicked)
+        );
+        assert_eq!(pool.stats().running, 0);
+    }
+}
@@ -1,62 +1,728 @@
+use crate::pool::{BlockingPool, PoolError};
+use crate::seek_index::SeekIndex;
 use crate::types::*;
 use crate::*;
-use flate2::read::GzDecoder;
+use async_trait::async_trait;
+use flate2::read::MultiGzDecoder;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::{Bucket, Region};
 use std::collections::HashMap;
-use std::io::Read;
+use std::io::{Read, SeekFrom};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 const TARGET: &str = "reader";
+/// The maximum time to connect to a remote archive store.
+const STORE_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// The maximum time of a request to a remote archive store, so a stalled store doesn't hang the
+/// block requests. A timed out read is reported as `ArchiveError::Overloaded`.
+const STORE_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// Storage of the archive files.
+///
+/// The files are addressed by keys relative to the root of the store, see `archive_filename`.
+/// The reads that time out fail with `std::io::ErrorKind::TimedOut`.
+#[async_trait]
+pub trait BlockArchiveStore: Send + Sync {
+    /// Reads the whole archive file. Returns `None` if the file doesn't exist.
+    async fn read(&self, key: &str) -> std::io::Result<Option<Vec<u8>>>;
+
+    /// Reads `length` bytes of the archive file starting from `offset`. Returns `None` if the file
+    /// doesn't exist.
+    async fn read_range(
+        &self,
+        key: &str,
+        offset: u64,
+        length: u64,
+    ) -> std::io::Result<Option<Vec<u8>>>;
+}
+
+/// Archive files stored on the local filesystem under the given path.
+pub struct FsArchiveStore {
+    path: String,
+}
+
+impl FsArchiveStore {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl BlockArchiveStore for FsArchiveStore {
+    async fn read(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(format!("{}/{}", self.path, key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn read_range(
+        &self,
+        key: &str,
+        offset: u64,
+        length: u64,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let mut file = match tokio::fs::File::open(format!("{}/{}", self.path, key)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        file.seek(SeekFrom::Start(offset)).await?;
+        // The buffer grows with the data read, so a range past the end of the file doesn't
+        // allocate its whole length
+        let mut data = vec![];
+        file.take(length).read_to_end(&mut data).await?;
+        if (data.len() as u64) < length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "The range is past the end of the file",
+            ));
+        }
+        Ok(Some(data))
+    }
+}
+
+/// Archive files stored in an S3-compatible bucket under the given prefix.
+pub struct S3ArchiveStore {
+    bucket: Box<Bucket>,
+    prefix: String,
+}
+
+impl S3ArchiveStore {
+    /// Creates the store with the credentials from the environment or the instance profile. Without
+    /// credentials, the bucket is read anonymously, e.g. a public bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket` - The name of the bucket.
+    /// * `prefix` - The prefix of the keys in the bucket, can be empty.
+    /// * `region` - The region of the bucket.
+    /// * `endpoint` - The custom endpoint of an S3-compatible service. Uses path-style requests.
+    pub fn new(
+        bucket: &str,
+        prefix: &str,
+        region: &str,
+        endpoint: Option<String>,
+    ) -> Result<Self, String> {
+        let credentials = Credentials::default()
+            .or_else(|e| {
+                tracing::warn!(target: TARGET, "No S3 credentials, reading the bucket anonymously: {}", e);
+                Credentials::anonymous()
+            })
+            .map_err(|e| e.to_string())?;
+        let bucket = match endpoint {
+            Some(endpoint) => {
+                let region = Region::Custom {
+                    region: region.to_string(),
+                    endpoint,
+                };
+                Bucket::new(bucket, region, credentials)
+                    .map_err(|e| e.to_string())?
+                    .with_path_style()
+            }
+            None => {
+                let region: Region = region.parse().map_err(|e| format!("{:?}", e))?;
+                Bucket::new(bucket, region, credentials).map_err(|e| e.to_string())?
+            }
+        };
+        Ok(Self {
+            bucket,
+            prefix: prefix.trim_matches('/').to_string(),
+        }
+        .with_request_timeout(STORE_REQUEST_TIMEOUT))
+    }
+
+    /// Sets the maximum time of a request to the bucket.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.bucket.set_request_timeout(Some(timeout));
+        self
+    }
+
+    fn path(&self, key: &str) -> String {
+        if self.prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", self.prefix, key)
+        }
+    }
+}
+
+#[async_trait]
+impl BlockArchiveStore for S3ArchiveStore {
+    async fn read(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let path = self.path(key);
+        let response = self.bucket.get_object(&path).await.map_err(s3_error)?;
+        match response.status_code() {
+            200 => Ok(Some(response.to_vec())),
+            404 => Ok(None),
+            status_code => Err(std::io::Error::other(format!(
+                "Unexpected status code {} for {}",
+                status_code, path
+            ))),
+        }
+    }
+
+    async fn read_range(
+        &self,
+        key: &str,
+        offset: u64,
+        length: u64,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let path = self.path(key);
+        let response = self
+            .bucket
+            .get_object_range(&path, offset, Some(offset + length - 1))
+            .await
+            .map_err(s3_error)?;
+        match response.status_code() {
+            206 => Ok(Some(response.to_vec())),
+            404 => Ok(None),
+            status_code => Err(std::io::Error::other(format!(
+                "Unexpected status code {} for {}",
+                status_code, path
+            ))),
+        }
+    }
+}
+
+fn s3_error(e: S3Error) -> std::io::Error {
+    match e {
+        S3Error::Reqwest(e) if e.is_timeout() => {
+            std::io::Error::new(std::io::ErrorKind::TimedOut, e)
+        }
+        e => std::io::Error::other(e),
+    }
+}
+
+/// Archive files served by an HTTP(S) origin under the given base URL.
+pub struct HttpArchiveStore {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpArchiveStore {
+    pub fn new(base_url: String) -> Self {
+        Self::with_request_timeout(base_url, STORE_REQUEST_TIMEOUT)
+    }
+
+    /// Creates the store with the given maximum time of a request to the origin.
+    pub fn with_request_timeout(base_url: String, timeout: Duration) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .connect_timeout(STORE_CONNECT_TIMEOUT.min(timeout))
+                .timeout(timeout)
+                .build()
+                .expect("Failed to create the HTTP client"),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+fn http_error(e: reqwest::Error) -> std::io::Error {
+    if e.is_timeout() {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, e)
+    } else {
+        std::io::Error::other(e)
+    }
+}
+
+#[async_trait]
+impl BlockArchiveStore for HttpArchiveStore {
+    async fn read(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let response = self
+            .client
+            .get(format!("{}/{}", self.base_url, key))
+            .send()
+            .await
+            .map_err(http_error)?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let data = response
+            .error_for_status()
+            .map_err(http_error)?
+            .bytes()
+            .await
+            .map_err(http_error)?;
+        Ok(Some(data.to_vec()))
+    }
+
+    async fn read_range(
+        &self,
+        key: &str,
+        offset: u64,
+        length: u64,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let response = self
+            .client
+            .get(format!("{}/{}", self.base_url, key))
+            .header(RANGE, format!("bytes={}-{}", offset, offset + length - 1))
+            .send()
+            .await
+            .map_err(http_error)?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response.error_for_status().map_err(http_error)?;
+        let is_partial = response.status() == StatusCode::PARTIAL_CONTENT;
+        let data = response.bytes().await.map_err(http_error)?;
+        if is_partial {
+            return Ok(Some(data.to_vec()));
+        }
+        // The origin ignored the range and returned the whole file
+        data.get(offset as usize..(offset + length) as usize)
+            .map(|data| Some(data.to_vec()))
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+    }
+}
+
+/// Returns the key of the archive file containing the block, relative to the archive store.
 pub fn archive_filename(
     config: &ReadConfig,
     chain_id: ChainId,
     block_height: BlockHeight,
+) -> String {
+    archive_key(config, chain_id, block_height, "tgz")
+}
+
+/// Returns the key of the seek index of the archive file containing the block.
+pub fn seek_index_filename(
+    config: &ReadConfig,
+    chain_id: ChainId,
+    block_height: BlockHeight,
+) -> String {
+    archive_key(config, chain_id, block_height, "idx")
+}
+
+fn archive_key(
+    config: &ReadConfig,
+    chain_id: ChainId,
+    block_height: BlockHeight,
+    extension: &str,
 ) -> String {
     let starting_block = block_height / config.save_every_n * config.save_every_n;
     let padded_block_height = format!("{:0>12}", starting_block);
     format!(
-        "{}/{}/{}/{}/{}.tgz",
-        config.path,
+        "{}/{}/{}/{}.{}",
         chain_id,
         &padded_block_height[..6],
         &padded_block_height[6..9],
-        padded_block_height
+        padded_block_height,
+        extension
     )
 }
 
-pub fn read_blocks(
+/// A block read from an archive file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveBlock {
+    Block(String),
+    /// The archive file is readable, but doesn't have the block, so the block was skipped on chain.
+    Skipped,
+    /// The block can't be read, so it's unknown whether it exists.
+    Unavailable(ArchiveError),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// The archive file doesn't exist or can't be read from the store.
+    FileMissing,
+    /// The archive file or the block's entry is corrupt.
+    Corrupt,
+    /// The archive reads are saturated or the store timed out, so the archive file wasn't decoded.
+    Overloaded,
+}
+
+impl ArchiveBlock {
+    /// Returns the block to cache: the block or an empty string for a skipped block.
+    /// Unavailable blocks are not cached.
+    pub fn cached(&self) -> Option<String> {
+        match self {
+            ArchiveBlock::Block(block) => Some(block.clone()),
+            ArchiveBlock::Skipped => Some(String::new()),
+            ArchiveBlock::Unavailable(_) => None,
+        }
+    }
+}
+
+/// Reads a single block from a seekable archive file, decompressing only the block's entry.
+///
+/// # Returns
+///
+/// `None` if the archive file doesn't have a seek index (or it can't be read), so the whole archive
+/// has to be read with `read_blocks`. Otherwise, the block, a skipped block, or an unavailable
+/// block if the seek index is corrupt, e.g. its entry is too long or past the end of the archive.
+pub async fn read_block(
     config: &ReadConfig,
+    pool: &BlockingPool,
     chain_id: ChainId,
     block_height: BlockHeight,
-) -> Vec<(BlockHeight, Option<String>)> {
+) -> Option<ArchiveBlock> {
+    let index_filename = seek_index_filename(config, chain_id, block_height);
+    let seek_index = match config.store.read(&index_filename).await {
+        Ok(Some(data)) => SeekIndex::decode(&data),
+        Ok(None) => return None,
+        Err(e) => Err(e),
+    };
+    let seek_index = match seek_index {
+        Ok(seek_index) => seek_index,
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            tracing::error!(target: TARGET, "The seek index {} is corrupt: {:?}", index_filename, e);
+            return Some(ArchiveBlock::Unavailable(ArchiveError::Corrupt));
+        }
+        Err(e) => {
+            tracing::error!(target: TARGET, "Failed to read {}: {:?}", index_filename, e);
+            return None;
+        }
+    };
+    let starting_block = block_height / config.save_every_n * config.save_every_n;
+    if seek_index.starting_block != starting_block {
+        tracing::error!(target: TARGET, "The seek index {} starts at block {} instead of {}", index_filename, seek_index.starting_block, starting_block);
+        return None;
+    }
+    let Some((offset, length)) = seek_index.get(block_height) else {
+        return Some(ArchiveBlock::Skipped);
+    };
+
+    let filename = archive_filename(config, chain_id, block_height);
+    tracing::debug!(target: TARGET, "Reading block {} from {}", block_height, filename);
+    let data = match config.store.read_range(&filename, offset, length).await {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            tracing::error!(target: TARGET, "File not found: {}", filename);
+            return None;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            tracing::error!(target: TARGET, "The seek index entry of block {} is past the end of {}", block_height, filename);
+            return Some(ArchiveBlock::Unavailable(ArchiveError::Corrupt));
+        }
+        Err(e) => {
+            tracing::error!(target: TARGET, "Failed to read {}: {:?}", filename, e);
+            return None;
+        }
+    };
+    let key = format!("{:0>12}.json", block_height);
+    let block = match pool
+        .run(move || {
+            let (_, block) = read_archive_entries(&data)
+                .ok()?
+                .into_iter()
+                .find_map(|entry| entry.ok().filter(|(path, _)| *path == key))?;
+            Some(archive_block(block))
+        })
+        .await
+    {
+        Ok(block) => block,
+        Err(e) => {
+            tracing::warn!(target: TARGET, "Failed to decode block {} from {}: {:?}", block_height, filename, e);
+            return None;
+        }
+    };
+    let Some(block) = block else {
+        tracing::error!(target: TARGET, "Block {} is not found at the indexed offset in {}", block_height, filename);
+        return None;
+    };
+    if block == ArchiveBlock::Unavailable(ArchiveError::Corrupt) {
+        tracing::error!(target: TARGET, "Block {} is not valid JSON in {}", block_height, filename);
+    }
+    Some(block)
+}
+
+pub async fn read_blocks(
+    config: &ReadConfig,
+    pool: &BlockingPool,
+    chain_id: ChainId,
+    block_height: BlockHeight,
+) -> Vec<(BlockHeight, ArchiveBlock)> {
     let starting_block = block_height / config.save_every_n * config.save_every_n;
     let filename = archive_filename(config, chain_id, block_height);
 
     tracing::debug!(target: TARGET, "Reading blocks from {}", filename);
 
-    let mut blocks = read_archive(&filename);
+    let data = match config.store.read(&filename).await {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            tracing::error!(target: TARGET, "File not found: {}", filename);
+            return unavailable_blocks(
+                config.save_every_n,
+                starting_block,
+                ArchiveError::FileMissing,
+            );
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+            tracing::warn!(target: TARGET, "Timed out reading {}: {:?}", filename, e);
+            return unavailable_blocks(
+                config.save_every_n,
+                starting_block,
+                ArchiveError::Overloaded,
+            );
+        }
+        Err(e) => {
+            tracing::error!(target: TARGET, "Failed to read {}: {:?}", filename, e);
+            return unavailable_blocks(
+                config.save_every_n,
+                starting_block,
+                ArchiveError::FileMissing,
+            );
+        }
+    };
+    let save_every_n = config.save_every_n;
+    let blocks = match pool
+        .run(move || archive_blocks(save_every_n, starting_block, &data))
+        .await
+    {
+        Ok(blocks) => blocks,
+        Err(PoolError::Panicked) => {
+            tracing::error!(target: TARGET, "Failed to decode {}", filename);
+            unavailable_blocks(save_every_n, starting_block, ArchiveError::Corrupt)
+        }
+        Err(e) => {
+            tracing::warn!(target: TARGET, "Failed to decode {}: {:?}", filename, e);
+            unavailable_blocks(save_every_n, starting_block, ArchiveError::Overloaded)
+        }
+    };
+    if blocks
+        .iter()
+        .any(|(_, block)| *block == ArchiveBlock::Unavailable(ArchiveError::Corrupt))
+    {
+        tracing::error!(target: TARGET, "The archive {} is corrupt", filename);
+    }
+    blocks
+}
+
+fn unavailable_blocks(
+    save_every_n: u64,
+    starting_block: BlockHeight,
+    error: ArchiveError,
+) -> Vec<(BlockHeight, ArchiveBlock)> {
+    (starting_block..starting_block + save_every_n)
+        .map(|block_height| (block_height, ArchiveBlock::Unavailable(error)))
+        .collect()
+}
+
+/// Decodes the blocks of an archive file.
+///
+/// If some entries of the archive can't be read, the blocks without readable entries are
+/// unavailable, since they can't be told apart from the skipped blocks. The entries that aren't
+/// valid JSON are unavailable as well.
+///
+/// # Arguments
+///
+/// * `save_every_n` - The number of blocks in the archive file.
+/// * `starting_block` - The first block height of the archive file.
+/// * `data` - The gzipped tar archive.
+pub fn archive_blocks(
+    save_every_n: u64,
+    starting_block: BlockHeight,
+    data: &[u8],
+) -> Vec<(BlockHeight, ArchiveBlock)> {
+    let Ok(entries) = read_archive_entries(data) else {
+        return unavailable_blocks(save_every_n, starting_block, ArchiveError::Corrupt);
+    };
+    let mut is_corrupt = false;
+    let mut blocks = HashMap::new();
+    for entry in entries {
+        match entry {
+            Ok((path, content)) => {
+                blocks.insert(path, content);
+            }
+            Err(_) => is_corrupt = true,
+        }
+    }
     let mut result = Vec::new();
-    for i in 0..config.save_every_n {
+    for i in 0..save_every_n {
         let block_height = starting_block + i;
         let key = format!("{:0>12}.json", block_height);
-        result.push((block_height, blocks.remove(&key)));
+        let block = match blocks.remove(&key) {
+            Some(block) => archive_block(block),
+            None if is_corrupt => ArchiveBlock::Unavailable(ArchiveError::Corrupt),
+            None => ArchiveBlock::Skipped,
+        };
+        result.push((block_height, block));
     }
     result
 }
 
-fn read_archive(path: &str) -> HashMap<String, String> {
-    if !std::path::Path::new(path).exists() {
-        tracing::error!(target: TARGET, "File not found: {}", path);
-        return HashMap::new();
+/// Returns the block of an archive entry. An entry that isn't valid JSON, e.g. a truncated one, is
+/// corrupt, so it isn't served and cached as a block.
+fn archive_block(block: String) -> ArchiveBlock {
+    match serde_json::from_str::<&serde_json::value::RawValue>(&block) {
+        Ok(_) => ArchiveBlock::Block(block),
+        Err(_) => ArchiveBlock::Unavailable(ArchiveError::Corrupt),
     }
-    tar::Archive::new(GzDecoder::new(std::fs::File::open(path).unwrap()))
-        .entries()
-        .unwrap()
-        .filter_map(|e| e.ok())
-        .map(|mut e| {
-            let path = e.path().unwrap().to_string_lossy().to_string();
+}
+
+/// Reads the file names and the contents of all entries of the archive.
+/// The unreadable entries are returned as errors.
+pub fn read_archive_entries(
+    data: &[u8],
+) -> std::io::Result<Vec<std::io::Result<(String, String)>>> {
+    // Seekable archives consist of multiple gzip members
+    Ok(tar::Archive::new(MultiGzDecoder::new(data))
+        .entries()?
+        .map(|e| {
+            let mut e = e?;
+            let path = e.path()?.to_string_lossy().to_string();
             let mut content = String::new();
-            e.read_to_string(&mut content).unwrap();
-            (path, content)
+            e.read_to_string(&mut content)?;
+            Ok((path, content))
         })
-        .collect()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::encode_archive;
+    use std::sync::Arc;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    const DATA: &[u8] = b"0123456789";
+
+    /// Serves the files at the given paths over HTTP like an S3-compatible storage, supporting
+    /// single byte ranges. The requests to the paths starting with `/stall` never get a response.
+    async fn serve(files: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let files = Arc::new(files);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let files = files.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).to_string();
+                    let path = request.split(' ').nth(1).unwrap_or_default();
+                    let path = path.split('?').next().unwrap_or_default();
+                    if path.starts_with("/stall") {
+                        tokio::time::sleep(Duration::from_secs(60)).await;
+                        return;
+                    }
+                    let range = request.lines().find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        let (start, end) = name
+                            .eq_ignore_ascii_case("range")
+                            .then(|| value.trim().strip_prefix("bytes="))??
+                            .split_once('-')?;
+                        Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+                    });
+                    let (status, body) = match (files.get(path), range) {
+                        (Some(data), Some((start, end))) => {
+                            ("206 Partial Content", data[start..=end].to_vec())
+                        }
+                        (Some(data), None) => ("200 OK", data.clone()),
+                        (None, _) => ("404 Not Found", Vec::new()),
+                    };
+                    let head = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    let _ = stream.write_all(&body).await;
+                });
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    async fn assert_store_reads(store: &dyn BlockArchiveStore) {
+        assert_eq!(store.read("file.tgz").await.unwrap(), Some(DATA.to_vec()));
+        assert_eq!(
+            store.read_range("file.tgz", 2, 3).await.unwrap(),
+            Some(b"234".to_vec())
+        );
+        assert_eq!(store.read("missing.tgz").await.unwrap(), None);
+        assert_eq!(store.read_range("missing.tgz", 2, 3).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_http_archive_store() {
+        let url = serve(HashMap::from([(
+            "/blocks/file.tgz".to_string(),
+            DATA.to_vec(),
+        )]))
+        .await;
+        assert_store_reads(&HttpArchiveStore::new(format!("{}/blocks/", url))).await;
+
+        let store = HttpArchiveStore::with_request_timeout(
+            format!("{}/stall", url),
+            Duration::from_millis(200),
+        );
+        let err = store.read("file.tgz").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        let err = store.read_range("file.tgz", 2, 3).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn test_s3_archive_store() {
+        let url = serve(HashMap::from([(
+            "/bucket/prefix/file.tgz".to_string(),
+            DATA.to_vec(),
+        )]))
+        .await;
+        let store =
+            S3ArchiveStore::new("bucket", "/prefix/", "us-east-1", Some(url.clone())).unwrap();
+        assert_store_reads(&store).await;
+
+        let store = S3ArchiveStore::new("stall", "", "us-east-1", Some(url))
+            .unwrap()
+            .with_request_timeout(Duration::from_millis(200));
+        let err = store.read("file.tgz").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn test_read_blocks_timeout_is_overloaded() {
+        let url = serve(HashMap::new()).await;
+        let config = ReadConfig {
+            store: Arc::new(HttpArchiveStore::with_request_timeout(
+                format!("{}/stall", url),
+                Duration::from_millis(200),
+            )),
+            save_every_n: 2,
+        };
+        let pool = BlockingPool::new(1, 1, Duration::from_secs(10));
+        let blocks = read_blocks(&config, &pool, ChainId::Mainnet, 100).await;
+        assert_eq!(
+            blocks,
+            vec![
+                (100, ArchiveBlock::Unavailable(ArchiveError::Overloaded)),
+                (101, ArchiveBlock::Unavailable(ArchiveError::Overloaded)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_archive_blocks_invalid_json() {
+        let blocks = vec![
+            (100, Some(r#"{"block":100}"#.to_string())),
+            (101, Some(r#"{"block":"#.to_string())),
+            (102, None),
+            (103, Some(String::new())),
+        ];
+        let (data, _) = encode_archive(100, &blocks).unwrap();
+        assert_eq!(
+            archive_blocks(4, 100, &data),
+            vec![
+                (100, ArchiveBlock::Block(r#"{"block":100}"#.to_string())),
+                (101, ArchiveBlock::Unavailable(ArchiveError::Corrupt)),
+                (102, ArchiveBlock::Skipped),
+                (103, ArchiveBlock::Unavailable(ArchiveError::Corrupt)),
+            ]
+        );
+    }
 }
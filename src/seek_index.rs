@@ -0,0 +1,134 @@
+use crate::types::*;
+
+const MAGIC: &[u8; 8] = b"NDSEEK01";
+const HEADER_SIZE: usize = 24;
+const ENTRY_SIZE: usize = 16;
+/// The maximum length of a block's gzip member, far above the largest compressed block. The index
+/// isn't trusted to size the reads, since they are allocated up front.
+pub const MAX_ENTRY_LENGTH: u64 = 64 * 1024 * 1024;
+
+/// Sidecar index of a seekable archive file, stored next to the archive as `.idx`.
+///
+/// A seekable archive is a regular `.tgz` file where every tar entry (the header and the content
+/// of one block) is compressed as a separate gzip member. Gzip allows concatenated members, so the
+/// file remains a valid `.tgz`, while a single block can be decompressed from its member alone.
+///
+/// The index is little-endian: the `NDSEEK01` magic, the first block height and the number of
+/// blocks as `u64`, then the `u64` offset and the `u64` length of the gzip member of every block.
+/// A zero length means the block is missing from the archive.
+#[derive(Debug, Clone)]
+pub struct SeekIndex {
+    pub starting_block: BlockHeight,
+    /// The offset and the length of the gzip member of each block, starting from `starting_block`.
+    pub entries: Vec<(u64, u64)>,
+}
+
+impl SeekIndex {
+    pub fn decode(data: &[u8]) -> std::io::Result<Self> {
+        let invalid_data =
+            |error: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string());
+        if data.len() < HEADER_SIZE || &data[..8] != MAGIC {
+            return Err(invalid_data("Invalid seek index header"));
+        }
+        let starting_block = read_u64(&data[8..16]);
+        let entries_data = &data[HEADER_SIZE..];
+        let expected_length = usize::try_from(read_u64(&data[16..24]))
+            .ok()
+            .and_then(|count| count.checked_mul(ENTRY_SIZE));
+        if expected_length != Some(entries_data.len()) {
+            return Err(invalid_data("Invalid seek index length"));
+        }
+        let entries: Vec<(u64, u64)> = entries_data
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| (read_u64(&entry[..8]), read_u64(&entry[8..])))
+            .collect();
+        if entries.iter().any(|(offset, length)| {
+            offset.checked_add(*length).is_none() || *length > MAX_ENTRY_LENGTH
+        }) {
+            return Err(invalid_data("Invalid seek index entry"));
+        }
+        Ok(Self {
+            starting_block,
+            entries,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_SIZE + self.entries.len() * ENTRY_SIZE);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&self.starting_block.to_le_bytes());
+        data.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for (offset, length) in &self.entries {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&length.to_le_bytes());
+        }
+        data
+    }
+
+    /// Returns the offset and the length of the gzip member of the block, or `None` if the block
+    /// is missing from the archive.
+    pub fn get(&self, block_height: BlockHeight) -> Option<(u64, u64)> {
+        let i = block_height.checked_sub(self.starting_block)? as usize;
+        self.entries
+            .get(i)
+            .copied()
+            .filter(|(_, length)| *length > 0)
+    }
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    u64::from_le_bytes(data.try_into().expect("Invalid u64 length"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(starting_block: BlockHeight, count: u64) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&starting_block.to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let seek_index = SeekIndex {
+            starting_block: 1000,
+            entries: vec![(0, 10), (10, 0), (10, 20)],
+        };
+        let decoded = SeekIndex::decode(&seek_index.encode()).unwrap();
+        assert_eq!(decoded.starting_block, 1000);
+        assert_eq!(decoded.entries, seek_index.entries);
+        assert_eq!(decoded.get(999), None);
+        assert_eq!(decoded.get(1000), Some((0, 10)));
+        assert_eq!(decoded.get(1001), None);
+        assert_eq!(decoded.get(1002), Some((10, 20)));
+        assert_eq!(decoded.get(1003), None);
+    }
+
+    #[test]
+    fn test_decode_corrupt() {
+        let is_invalid_data = |data: &[u8]| {
+            SeekIndex::decode(data).unwrap_err().kind() == std::io::ErrorKind::InvalidData
+        };
+        assert!(is_invalid_data(b"NDSEEK01"));
+        assert!(is_invalid_data(&header(1000, 1)));
+        // The entries length overflows
+        assert!(is_invalid_data(&header(1000, u64::MAX)));
+        assert!(is_invalid_data(&header(
+            1000,
+            u64::MAX / ENTRY_SIZE as u64 + 1
+        )));
+        // The entry's end overflows
+        let mut data = header(1000, 1);
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes());
+        assert!(is_invalid_data(&data));
+        // The entry is too long
+        let mut data = header(1000, 1);
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&(MAX_ENTRY_LENGTH + 1).to_le_bytes());
+        assert!(is_invalid_data(&data));
+    }
+}
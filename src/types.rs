@@ -1,3 +1,4 @@
+use serde::Deserialize;
 use std::fmt::Display;
 
 pub type BlockHeight = u64;
@@ -29,7 +30,8 @@ impl TryFrom<String> for ChainId {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
 pub enum Finality {
     Final,
     Optimistic,
@@ -0,0 +1,201 @@
+use crate::seek_index::SeekIndex;
+use crate::types::*;
+use flate2::write::GzEncoder;
+use std::io::Write;
+use std::path::Path;
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Encodes the blocks of one archive file as a seekable archive, see `SeekIndex`.
+/// Missing blocks are not added to the archive.
+///
+/// # Arguments
+///
+/// * `starting_block` - The first block height of the archive file.
+/// * `blocks` - The blocks of the archive file in order of block height.
+///
+/// # Returns
+///
+/// The gzipped tar archive and its seek index.
+pub fn encode_archive(
+    starting_block: BlockHeight,
+    blocks: &[(BlockHeight, Option<String>)],
+) -> std::io::Result<(Vec<u8>, SeekIndex)> {
+    let mut data = Vec::new();
+    let mut entries = Vec::with_capacity(blocks.len());
+    for (block_height, block) in blocks {
+        let Some(block) = block else {
+            entries.push((0, 0));
+            continue;
+        };
+        let mut header = tar::Header::new_gnu();
+        header.set_path(format!("{:0>12}.json", block_height))?;
+        header.set_size(block.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        let mut entry = header.as_bytes().to_vec();
+        entry.extend_from_slice(block.as_bytes());
+        entry.resize(entry.len().next_multiple_of(TAR_BLOCK_SIZE), 0);
+
+        let member = gzip(&entry)?;
+        entries.push((data.len() as u64, member.len() as u64));
+        data.extend(member);
+    }
+    // The end of the tar archive
+    data.extend(gzip(&[0; 2 * TAR_BLOCK_SIZE])?);
+    Ok((
+        data,
+        SeekIndex {
+            starting_block,
+            entries,
+        },
+    ))
+}
+
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Writes the file atomically by writing a temporary file next to it and renaming it.
+pub fn write_file_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::BlockingPool;
+    use crate::reader::{
+        archive_filename, read_archive_entries, read_block, seek_index_filename, ArchiveBlock,
+        ArchiveError, FsArchiveStore,
+    };
+    use crate::ReadConfig;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const STARTING_BLOCK: BlockHeight = 1000;
+
+    fn blocks() -> Vec<(BlockHeight, Option<String>)> {
+        vec![
+            (1000, Some(r#"{"block":1000}"#.to_string())),
+            (1001, None),
+            (
+                1002,
+                Some(format!(r#""{}""#, "x".repeat(TAR_BLOCK_SIZE + 1))),
+            ),
+            (1003, Some("{}".to_string())),
+            (1004, None),
+        ]
+    }
+
+    #[test]
+    fn test_encode_archive_round_trip() {
+        let blocks = blocks();
+        let (data, seek_index) = encode_archive(STARTING_BLOCK, &blocks).unwrap();
+
+        let entries: Vec<(String, String)> = read_archive_entries(&data)
+            .unwrap()
+            .into_iter()
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        let expected: Vec<(String, String)> = blocks
+            .iter()
+            .filter_map(|(block_height, block)| {
+                let block = block.clone()?;
+                Some((format!("{:0>12}.json", block_height), block))
+            })
+            .collect();
+        assert_eq!(entries, expected);
+
+        let seek_index = SeekIndex::decode(&seek_index.encode()).unwrap();
+        assert_eq!(seek_index.starting_block, STARTING_BLOCK);
+        for (block_height, block) in &blocks {
+            let Some(block) = block else {
+                assert_eq!(seek_index.get(*block_height), None);
+                continue;
+            };
+            let (offset, length) = seek_index.get(*block_height).unwrap();
+            let member = &data[offset as usize..(offset + length) as usize];
+            let entries = read_archive_entries(member).unwrap();
+            assert_eq!(entries.len(), 1);
+            let (path, content) = entries.into_iter().next().unwrap().unwrap();
+            assert_eq!(path, format!("{:0>12}.json", block_height));
+            assert_eq!(&content, block);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_block_from_encoded_archive() {
+        let path =
+            std::env::temp_dir().join(format!("neardata-writer-test-{}", std::process::id()));
+        let config = ReadConfig {
+            store: Arc::new(FsArchiveStore::new(path.to_string_lossy().to_string())),
+            save_every_n: blocks().len() as u64,
+        };
+        let chain_id = ChainId::Mainnet;
+        let (data, seek_index) = encode_archive(STARTING_BLOCK, &blocks()).unwrap();
+        write_file_atomically(
+            &path.join(archive_filename(&config, chain_id, STARTING_BLOCK)),
+            &data,
+        )
+        .unwrap();
+        write_file_atomically(
+            &path.join(seek_index_filename(&config, chain_id, STARTING_BLOCK)),
+            &seek_index.encode(),
+        )
+        .unwrap();
+
+        let pool = BlockingPool::new(1, 1, Duration::from_secs(10));
+        for (block_height, block) in blocks() {
+            let res = read_block(&config, &pool, chain_id, block_height).await;
+            let block = block.map_or(ArchiveBlock::Skipped, ArchiveBlock::Block);
+            assert_eq!(res, Some(block), "block {}", block_height);
+        }
+
+        // A seek index of another archive file is not trusted
+        let other_seek_index = SeekIndex {
+            starting_block: STARTING_BLOCK + config.save_every_n,
+            entries: seek_index.entries,
+        };
+        write_file_atomically(
+            &path.join(seek_index_filename(&config, chain_id, STARTING_BLOCK)),
+            &other_seek_index.encode(),
+        )
+        .unwrap();
+        assert_eq!(
+            read_block(&config, &pool, chain_id, STARTING_BLOCK).await,
+            None
+        );
+
+        // The entries past the end of the archive or too long to be a block are corrupt
+        for length in [data.len() as u64 + 1, u64::MAX / 2] {
+            let corrupt_seek_index = SeekIndex {
+                starting_block: STARTING_BLOCK,
+                entries: vec![(0, length)],
+            };
+            write_file_atomically(
+                &path.join(seek_index_filename(&config, chain_id, STARTING_BLOCK)),
+                &corrupt_seek_index.encode(),
+            )
+            .unwrap();
+            assert_eq!(
+                read_block(&config, &pool, chain_id, STARTING_BLOCK).await,
+                Some(ArchiveBlock::Unavailable(ArchiveError::Corrupt)),
+                "length {}",
+                length
+            );
+        }
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}